@@ -15,7 +15,7 @@ use unico_ful::{
     asym::{Gn, YieldHandle},
     sym::PanicHook,
 };
-use unico_stack::Stack;
+use unico_stack::IntoStack;
 
 /// A [`Future`] based on a stackful generator.
 ///
@@ -34,7 +34,7 @@ pub struct AsymContext<'y> {
 impl<'a, F, T, S, P> Build<F, S, P> for Asym<'a, T>
 where
     F: FnOnce(AsymContext<'_>) -> T + Send + 'a,
-    S: Into<Stack>,
+    S: IntoStack<'a>,
     P: PanicHook,
 {
     fn build(builder: Builder<S, P>, arg: F) -> Result<Self, Self::Error> {
@@ -43,10 +43,10 @@ where
     }
 }
 
-impl<F, T, S, P> BuildUnchecked<F, S, P> for Asym<'_, T>
+impl<'a, F, T, S, P> BuildUnchecked<F, S, P> for Asym<'a, T>
 where
     F: FnOnce(AsymContext<'_>) -> T,
-    S: Into<Stack>,
+    S: IntoStack<'a>,
     P: PanicHook,
 {
     type Error = NewError;
@@ -65,6 +65,16 @@ where
     }
 }
 
+impl<T> Asym<'_, T> {
+    /// The largest size of the stack of this future ever used, in bytes.
+    ///
+    /// See [`Gn::high_water_mark`] for more information.
+    #[cfg(feature = "paint")]
+    pub fn high_water_mark(&self) -> Option<usize> {
+        self.0.high_water_mark()
+    }
+}
+
 impl<T> Future for Asym<'_, T> {
     type Output = T;
 
@@ -139,18 +149,21 @@ where
 
 impl<'a, T, F: FnOnce(AsymContext<'_>) -> T + Send> AsymBuilder<'a, T, F> {
     /// Tries to build a stackful future.
-    pub fn try_into_future<S, P>(self) -> Result<Asym<'a, T>, NewError>
-    where
-        S: Into<Stack>,
-        P: PanicHook,
-    {
+    ///
+    /// Unlike [`IntoFuture::into_future`], the error is returned instead of
+    /// panicking, e.g. when the stack fails to be allocated.
+    pub fn try_into_future(self) -> Result<Asym<'a, T>, NewError> {
         self.try_into_future_with(Builder::new())
     }
 
     /// Builds a stackful future with a specified builder configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics on the errors of [`AsymBuilder::try_into_future_with`].
     pub fn into_future_with<S, P>(self, builder: Builder<S, P>) -> Asym<'a, T>
     where
-        S: Into<Stack>,
+        S: IntoStack<'a>,
         P: PanicHook,
     {
         self.try_into_future_with(builder)
@@ -163,7 +176,7 @@ impl<'a, T, F: FnOnce(AsymContext<'_>) -> T + Send> AsymBuilder<'a, T, F> {
         builder: Builder<S, P>,
     ) -> Result<Asym<'a, T>, NewError>
     where
-        S: Into<Stack>,
+        S: IntoStack<'a>,
         P: PanicHook,
     {
         builder.build(self.func)
@@ -175,6 +188,9 @@ impl<'a, T, F: FnOnce(AsymContext<'_>) -> T + Send> IntoFuture for AsymBuilder<'
 
     type IntoFuture = Asym<'a, T>;
 
+    /// # Panics
+    ///
+    /// Panics on the errors of [`AsymBuilder::try_into_future`].
     fn into_future(self) -> Self::IntoFuture {
         self.into_future_with(Builder::new())
     }
@@ -240,3 +256,74 @@ mod block_on {
         })
     }
 }
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use core::{
+        future::{Future, IntoFuture},
+        mem::MaybeUninit,
+        pin::{Pin, pin},
+        task::{Context, Poll},
+    };
+    use std::string::ToString;
+
+    use unico_ful::{Builder, NewError};
+    use unico_stack::{BorrowedStack, StaticStackPool};
+
+    use super::{AsymWait, block_on::block_on, sync_with};
+
+    /// Pending for the first poll, and ready afterwards.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    #[test]
+    fn wait_with() {
+        let future = sync_with(|mut cx| {
+            YieldOnce(false).wait_with(&mut cx);
+            YieldOnce(false).wait_with(&mut cx);
+            42
+        });
+        assert_eq!(block_on(pin!(future.into_future())), 42);
+    }
+
+    #[test]
+    fn borrowed_stack() {
+        let mut memory = [MaybeUninit::uninit(); 32768];
+        let builder = Builder::new().on(BorrowedStack::new(&mut memory));
+        let future = sync_with(|mut cx| {
+            YieldOnce(false).wait_with(&mut cx);
+            42
+        });
+        assert_eq!(block_on(pin!(future.into_future_with(builder))), 42);
+    }
+
+    #[test]
+    fn exhausted() {
+        static POOL: StaticStackPool<1, 32768> = StaticStackPool::new();
+        let future = sync_with(|_| 42).try_into_future_with(Builder::new().on(&POOL));
+        let future = future.unwrap();
+
+        // The only stack is in use.
+        let res = sync_with(|_| 0).try_into_future_with(Builder::new().on(&POOL));
+        let Err(err @ NewError::Alloc(layout)) = res else {
+            panic!("the stack is allocated");
+        };
+        assert!(layout.size() < 32768);
+        assert!(err.to_string().starts_with("failed to allocate a stack"));
+
+        assert_eq!(block_on(pin!(future)), 42);
+        assert_eq!(POOL.available(), 1);
+    }
+}
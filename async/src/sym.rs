@@ -20,7 +20,7 @@ use unico_ful::{
     Builder, NewError,
     sym::{Co, PanicHook},
 };
-use unico_stack::Stack;
+use unico_stack::IntoStack;
 
 pub use self::cx::{SchedContext, WakerRef};
 
@@ -96,6 +96,14 @@ macro_rules! impl_tuples {
 }
 all_tuples!(impl_tuples, 0, 12, A);
 
+// SAFETY: The signal mask is a "current" state of the thread.
+#[cfg(feature = "ucx")]
+unsafe impl Switch for unico_context::ucx::SigMask {
+    fn switch(self) -> Self {
+        unico_context::ucx::SigMask::switch(self)
+    }
+}
+
 /// A scheduler operating on [`Task`]s.
 ///
 /// This trait only concerns about "run queues", not "wait queues". The user
@@ -176,7 +184,7 @@ pub trait SchedulerExt: Scheduler {
     ) -> Result<Task<Self::Metadata>, NewError>
     where
         F: FnOnce(&mut SchedContext<Self>) + Send + 'static,
-        S: Into<Stack>,
+        S: IntoStack<'static>,
         P: PanicHook,
         Self: Send + 'static,
     {
@@ -222,16 +230,25 @@ impl<F: Future + Send + Sized> SymWait for F {}
 #[cfg(test)]
 mod tests {
     use alloc::{alloc::Global, collections::VecDeque, sync::Arc};
-    use std::println;
+    use core::{
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering::SeqCst},
+        task::{Context, Poll, Waker},
+    };
+    use std::{println, thread};
 
     use spin::Mutex;
-    use unico_context::{boost::Boost, global_resumer};
+    use unico_context::global_resumer;
     use unico_ful::Builder;
     use unico_stack::global_stack_allocator;
 
-    use super::{Scheduler, SchedulerExt, Task};
+    use super::{Scheduler, SchedulerExt, SymWait, Task};
 
-    global_resumer!(Boost);
+    // Miri can only run the thread-emulated backend.
+    #[cfg(not(miri))]
+    global_resumer!(unico_context::DefaultResumer);
+    #[cfg(miri)]
+    global_resumer!(unico_context::thread::Thread);
     global_stack_allocator!(Global);
 
     struct Fifo(Mutex<VecDeque<Task>>);
@@ -289,4 +306,55 @@ mod tests {
         p.unwrap().resume(|task| sched.enqueue(task));
         assert!(!sched.yield_now());
     }
+
+    #[derive(Default)]
+    struct Slot {
+        value: Option<u32>,
+        waker: Option<Waker>,
+    }
+
+    struct Recv(Arc<Mutex<Slot>>);
+
+    impl Future for Recv {
+        type Output = u32;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            let mut slot = self.0.lock();
+            match slot.value.take() {
+                Some(value) => Poll::Ready(value),
+                None => {
+                    slot.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cross_thread_wake() {
+        let sched = Arc::new(Fifo(Mutex::new(VecDeque::new())));
+        let slot = Arc::new(Mutex::new(Slot::default()));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let (s2, d2) = (slot.clone(), done.clone());
+        let task = sched.clone().spawn(Default::default(), (), move |cx| {
+            assert_eq!(Recv(s2).wait::<_, (), _>(cx), 42);
+            d2.store(true, SeqCst);
+        });
+        task.unwrap().resume(|task| sched.enqueue(task));
+
+        thread::spawn(move || {
+            let mut slot = slot.lock();
+            slot.value = Some(42);
+            if let Some(waker) = slot.waker.take() {
+                waker.wake();
+            }
+        })
+        .join()
+        .unwrap();
+
+        while !done.load(SeqCst) {
+            sched.yield_now();
+        }
+    }
 }
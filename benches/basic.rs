@@ -4,14 +4,17 @@ use criterion::{Criterion, async_executor::AsyncExecutor, black_box};
 use spin_on::spin_on;
 use tokio::runtime::Runtime;
 use unico::{
+    Builder,
     asym::{AsymWait, sync},
-    context::{boost::Boost, global_resumer},
-    stack::global_stack_allocator,
+    context::{DefaultResumer, global_resumer},
+    stack::{StackPool, global_stack_allocator},
 };
 
-global_resumer!(Boost);
+global_resumer!(DefaultResumer);
 global_stack_allocator!(ferroc::Ferroc);
 
+static POOL: StackPool<ferroc::Ferroc> = StackPool::new(ferroc::Ferroc);
+
 struct SpinOnExec;
 
 impl AsyncExecutor for SpinOnExec {
@@ -30,6 +33,11 @@ pub fn creation(c: &mut Criterion, rt: &Runtime) {
             b.to_async(SpinOnExec)
                 .iter(|| black_box(sync(|| {}).into_future()))
         })
+        .bench_function("unico+pool", |b| {
+            b.to_async(SpinOnExec).iter(|| {
+                black_box(sync(|| {}).into_future_with(Builder::new().on(&POOL)))
+            })
+        })
         .bench_function("raw+tokio", |b| {
             b.to_async(rt).iter(|| rt.spawn(black_box(async {})))
         })
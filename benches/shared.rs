@@ -0,0 +1,102 @@
+use criterion::{Criterion, black_box};
+use unico::{
+    Builder,
+    asym::{Gn, YieldHandle},
+    context::{DefaultResumer, global_resumer},
+    shared::{SharedGn, SharedStack},
+    stack::{DEFAULT_LAYOUT, Limited, Stack, global_stack_allocator},
+};
+
+global_resumer!(DefaultResumer);
+global_stack_allocator!(ferroc::Ferroc);
+
+static LIMITED: Limited<ferroc::Ferroc> = Limited::new(ferroc::Ferroc);
+
+/// The number of live generators in each benchmark.
+const COUNT: usize = 1000;
+
+/// Yields forever with some locals on the stack.
+fn body(y: &mut YieldHandle, (): ()) {
+    let mut local = black_box([0u64; 32]);
+    loop {
+        local.iter_mut().for_each(|x| *x += 1);
+        black_box(&local);
+        y.yield_(());
+    }
+}
+
+fn dedicated() -> Gn<'static, ()> {
+    let builder = Builder::new().on((&LIMITED, DEFAULT_LAYOUT));
+    builder.r#gen(body).unwrap()
+}
+
+fn shared(stack: &SharedStack) -> SharedGn<'static, ()> {
+    Builder::new().on(stack).gen_shared(body).unwrap()
+}
+
+/// Reports the memory taken by suspended generators of both modes.
+fn memory() {
+    let mut gens = (0..COUNT).map(|_| dedicated()).collect::<Vec<_>>();
+    for gn in &mut gens {
+        gn.resume(());
+    }
+    let bytes = LIMITED.usage().bytes;
+    println!("dedicated: {COUNT} generators take {bytes} bytes");
+    drop(gens);
+
+    let stack = SharedStack::new(Stack::from((&LIMITED, DEFAULT_LAYOUT)));
+    let mut gens = (0..COUNT).map(|_| shared(&stack)).collect::<Vec<_>>();
+    for gn in &mut gens {
+        gn.resume(());
+    }
+    let bytes = LIMITED.usage().bytes + stack.saved_bytes();
+    println!("shared: {COUNT} generators take {bytes} bytes");
+}
+
+/// Resumes all the generators in turn, so that every resumption of a shared
+/// generator moves the stacks.
+fn switching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("switching");
+    group
+        .bench_function("dedicated", |b| {
+            let mut gens = (0..COUNT).map(|_| dedicated()).collect::<Vec<_>>();
+            let mut index = 0;
+            b.iter(|| {
+                index = (index + 1) % COUNT;
+                gens[index].resume(())
+            })
+        })
+        .bench_function("shared", |b| {
+            let stack = SharedStack::default();
+            let mut gens = (0..COUNT).map(|_| shared(&stack)).collect::<Vec<_>>();
+            let mut index = 0;
+            b.iter(|| {
+                index = (index + 1) % COUNT;
+                gens[index].resume(())
+            })
+        })
+        .bench_function("shared+occupant", |b| {
+            let stack = SharedStack::default();
+            let mut gn = shared(&stack);
+            b.iter(|| gn.resume(()))
+        });
+    group.finish();
+}
+
+fn creation(c: &mut Criterion) {
+    let mut group = c.benchmark_group("creation");
+    group
+        .bench_function("dedicated", |b| b.iter(dedicated))
+        .bench_function("shared", |b| {
+            let stack = SharedStack::default();
+            b.iter(|| shared(&stack))
+        });
+    group.finish();
+}
+
+fn main() {
+    memory();
+    let mut c = Criterion::default();
+    switching(&mut c);
+    creation(&mut c);
+}
@@ -1,16 +1,39 @@
 fn main() {
+    println!("cargo::rustc-check-cfg=cfg(unico_boost_fallback)");
+
     #[cfg(feature = "boost")]
-    build_boost();
+    if !build_boost() {
+        // Fall back to the `ucx` backend if Boost doesn't support the target.
+        println!("cargo::rustc-cfg=unico_boost_fallback");
+        #[cfg(not(feature = "ucx"))]
+        build_ucx();
+    }
+    #[cfg(feature = "ucx")]
+    build_ucx();
 }
 
+#[cfg(any(feature = "boost", feature = "ucx"))]
+fn build_ucx() {
+    cc::Build::new().file("src/ucx.c").compile("libunico_ucx.a");
+}
+
+/// Builds the Boost assembly for the target, or returns `false` if the target
+/// is not supported.
+///
+/// Set `UNICO_CONTEXT_BOOST_FALLBACK` to force the fallback.
 #[cfg(feature = "boost")]
-fn build_boost() {
+fn build_boost() -> bool {
     use std::{
         env, fs,
         io::{BufRead, BufReader},
         path::{Path, PathBuf},
     };
 
+    println!("cargo::rerun-if-env-changed=UNICO_CONTEXT_BOOST_FALLBACK");
+    if env::var_os("UNICO_CONTEXT_BOOST_FALLBACK").is_some() {
+        return false;
+    }
+
     let target = env::var("TARGET").unwrap();
     let is_win_gnu = target.ends_with("windows-gnu");
     let is_win_msvc = target.ends_with("windows-msvc");
@@ -26,9 +49,7 @@ fn build_boost() {
         "loongarch64" => "loongarch64",
         "riscv64gc" => "riscv64",
         "s390x" => "s390x",
-        _ => {
-            panic!("Unsupported architecture: {}", target);
-        }
+        _ => return false,
     };
 
     let abi = match arch {
@@ -78,6 +99,9 @@ fn build_boost() {
     let file_name = [arch, "_", abi, "_", format, "_", asm, ".", ext].concat();
 
     let path = base_path.join(file_name);
+    if !path.exists() {
+        return false;
+    }
     config.file(&path);
 
     config.compile("libboost_context.a");
@@ -92,4 +116,5 @@ fn build_boost() {
         s,
     )
     .unwrap();
+    true
 }
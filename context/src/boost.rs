@@ -1,6 +1,6 @@
-use core::{mem, ptr::NonNull};
+use core::{fmt, mem, ptr::NonNull};
 
-use crate::{Entry, Map, Resume, stack_top};
+use crate::{Capabilities, Entry, Map, Resume, stack_top};
 
 const CONTEXT_SIZE: usize = include!(concat!(env!("OUT_DIR"), "/context_size.txt"));
 const CONTEXT_LEN: usize = CONTEXT_SIZE / mem::size_of::<usize>();
@@ -38,6 +38,24 @@ pub enum NewError {
     StackTooSmall,
 }
 
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::StackTooSmall => {
+                f.write_str("the stack is too small for a context")
+            }
+        }
+    }
+}
+
+impl core::error::Error for NewError {}
+
+impl From<NewError> for crate::NewError {
+    fn from(err: NewError) -> Self {
+        crate::NewError::Boost(err)
+    }
+}
+
 // SAFETY: `Fcx` is created from `stack`. See Boost's assembly file for more
 // information.
 unsafe impl Resume for Boost {
@@ -45,6 +63,12 @@ unsafe impl Resume for Boost {
 
     type NewError = NewError;
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new::<Fcx>()
+            .preserves_fp_state(true)
+            .migratable(true)
+    }
+
     unsafe fn new_on(
         &self,
         stack: NonNull<[u8]>,
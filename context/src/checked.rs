@@ -0,0 +1,528 @@
+//! A validation layer over other backends.
+//!
+//! [`Checked`] records every suspended context in a global registry, and checks
+//! each switch against it, panicking on misuses that would otherwise corrupt
+//! memory silently:
+//!
+//! - resuming a context twice, or one that is running or finished;
+//! - resuming a context created by another backend;
+//! - resuming a non-migratable context on another thread;
+//! - running out of the stack of a context;
+//! - receiving data not sent by a checked switch.
+//!
+//! Every switch takes a global lock and goes through [`Resume::resume_with`],
+//! so this layer is meant for debug builds only:
+//!
+//! ```
+//! # #[cfg(all(feature = "boost", not(miri)))] {
+//! use unico_context::{DefaultResumer, checked::Checked};
+//!
+//! #[cfg(debug_assertions)]
+//! unico_context::global_resumer!(Checked(DefaultResumer));
+//! #[cfg(not(debug_assertions))]
+//! unico_context::global_resumer!(DefaultResumer);
+//! # }
+//! ```
+//!
+//! Contexts must be created by [`Checked`] as well. In particular, a context
+//! left on some stack is forgotten only when another context is created on the
+//! same memory, so stacks of finished contexts should be released or reused
+//! through the same resumer.
+//!
+//! Contexts are told apart by their addresses, so those whose stacks are copied
+//! out and back to the same memory, e.g. the ones sharing one stack, cannot be
+//! checked.
+
+use core::{any::TypeId, cell::Cell, mem, ptr::NonNull};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread::{self, ThreadId},
+};
+
+use crate::{Capabilities, Entry, Map, Resume, Transfer};
+
+/// The record of a context.
+#[derive(Debug, Clone, Copy)]
+struct Info {
+    /// The type of the backend that created the context.
+    backend: TypeId,
+    /// The bounds of the stack, or `None` if unknown, e.g. of the root context
+    /// of some thread.
+    stack: Option<(usize, usize)>,
+    /// The thread that the context is bound to, or `None` if the context is
+    /// migratable or has never run.
+    owner: Option<ThreadId>,
+    migratable: bool,
+    /// The entry function of the context if it has never run.
+    entry: Option<Entry<()>>,
+}
+
+impl Info {
+    fn root<R: Resume>(rs: &R) -> Self {
+        let migratable = rs.capabilities().migratable;
+        Info {
+            backend: TypeId::of::<R>(),
+            stack: None,
+            owner: (!migratable).then(|| thread::current().id()),
+            migratable,
+            entry: None,
+        }
+    }
+
+    /// Checks that the current stack pointer is within the stack.
+    #[inline(never)]
+    fn check_stack(&self) {
+        let probe = 0u8;
+        let sp = (&raw const probe).addr();
+        if let Some((start, end)) = self.stack {
+            assert!(
+                (start..end).contains(&sp),
+                "the stack pointer {sp:#x} is out of the stack {start:#x}..{end:#x}",
+            );
+        }
+    }
+}
+
+/// The suspended contexts, keyed by their addresses.
+static REGISTRY: Mutex<BTreeMap<usize, Info>> = Mutex::new(BTreeMap::new());
+
+fn registry() -> MutexGuard<'static, BTreeMap<usize, Info>> {
+    // A failed check doesn't leave the registry inconsistent.
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The packets sent to fresh contexts but not received yet.
+static STARTS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+fn starts() -> MutexGuard<'static, BTreeSet<usize>> {
+    STARTS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+std::thread_local! {
+    /// The record of the context running on the current thread, or `None` if
+    /// the thread has never switched.
+    static CURRENT: Cell<Option<Info>> = const { Cell::new(None) };
+    /// Whether the last switch to a suspended context on the current thread is
+    /// made by [`Checked`].
+    static ARRIVED: Cell<bool> = const { Cell::new(false) };
+}
+
+// The thread-local variables are accessed in separate functions, in case that
+// their addresses are cached across a switch to another thread.
+
+#[inline(never)]
+fn current<R: Resume>(rs: &R) -> Info {
+    CURRENT.get().unwrap_or_else(|| Info::root(rs))
+}
+
+#[inline(never)]
+fn set_current(info: Info) {
+    CURRENT.set(Some(info));
+}
+
+#[inline(never)]
+fn set_arrived() {
+    ARRIVED.set(true);
+}
+
+#[inline(never)]
+fn arrived() -> bool {
+    ARRIVED.replace(false)
+}
+
+/// The data sent to the target context in every switch.
+struct Packet<C> {
+    from: Info,
+    target: Info,
+    data: *mut (),
+    map: Option<Map<C>>,
+}
+
+/// The [`Resume`] implementation that validates every operation on contexts
+/// before forwarding it to `R`.
+///
+/// See [the module-level documentation](self) for more information.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Checked<R>(pub R);
+
+impl<R: Resume> Checked<R> {
+    /// # Safety
+    ///
+    /// See [`Resume::resume_with`] for more information.
+    unsafe fn switch(
+        &self,
+        cx: NonNull<R::Context>,
+        data: *mut (),
+        map: Option<Map<R::Context>>,
+    ) -> Transfer<R::Context> {
+        let from = current(&self.0);
+        from.check_stack();
+
+        let target = registry().remove(&cx.addr().get());
+        let target = target.unwrap_or_else(|| {
+            panic!(
+                "{cx:p} is not a suspended context; it may be running, finished, \
+                resumed twice or not created by a checked resumer"
+            )
+        });
+        assert!(
+            target.backend == TypeId::of::<R>(),
+            "{cx:p} is created by another backend",
+        );
+        if let Some(owner) = target.owner {
+            let current = thread::current().id();
+            assert!(
+                owner == current,
+                "{cx:p} is bound to {owner:?} but resumed on {current:?}",
+            );
+        }
+
+        let fresh = target.entry.is_some();
+        let mut packet = Packet {
+            from,
+            target,
+            data,
+            map,
+        };
+        let data: *mut () = (&raw mut packet).cast();
+        // SAFETY: `packet` is moved out by the target before anything else.
+        let t = if fresh {
+            starts().insert(data.addr());
+            unsafe { self.0.resume(cx, data) }
+        } else {
+            unsafe { self.0.resume_with(cx, data, arrive::<R>) }
+        };
+        assert!(
+            arrived(),
+            "the context is resumed without a checked resumer"
+        );
+        t
+    }
+}
+
+/// Registers the previous context after a switch to `cx`, and executes the
+/// map function of the resumer if any.
+///
+/// Returns the record of the current context as well.
+///
+/// # Safety
+///
+/// `data` must point to a valid [`Packet`] sent by [`Checked::switch`].
+unsafe fn receive<R: Resume>(
+    cx: NonNull<R::Context>,
+    data: *mut (),
+) -> (Info, Transfer<R::Context>) {
+    // SAFETY: The packet is not accessed again, and may be gone after `map`.
+    let Packet {
+        from,
+        mut target,
+        data,
+        map,
+    } = unsafe { data.cast::<Packet<R::Context>>().read() };
+
+    if !target.migratable && target.owner.is_none() {
+        target.owner = Some(thread::current().id());
+    }
+    target.check_stack();
+    set_current(Info {
+        entry: None,
+        ..target
+    });
+    registry().insert(cx.addr().get(), from);
+
+    let t = match map {
+        // SAFETY: `map` is valid by the contract of `Resume::resume_with`.
+        Some(map) => unsafe { map(cx, data) },
+        None => Transfer {
+            context: Some(cx),
+            data,
+        },
+    };
+    (target, t)
+}
+
+/// The map function of every switch to a suspended context.
+///
+/// # Safety
+///
+/// See [`receive`] for more information.
+#[allow(improper_ctypes_definitions)]
+unsafe extern "C-unwind" fn arrive<R: Resume>(
+    cx: NonNull<R::Context>,
+    data: *mut (),
+) -> Transfer<R::Context> {
+    set_arrived();
+    // SAFETY: The contract is the same.
+    unsafe { receive::<R>(cx, data) }.1
+}
+
+/// The entry of every context created by [`Checked`], which calls the actual
+/// one recorded.
+///
+/// Some backends don't support map functions on fresh contexts, so the packet
+/// is received here instead.
+///
+/// # Safety
+///
+/// The context must be created by [`Checked::new_on`](Resume::new_on).
+unsafe extern "C" fn enter<R: Resume>(cx: NonNull<R::Context>, data: *mut ()) -> ! {
+    assert!(
+        starts().remove(&data.addr()),
+        "a fresh context is started without a checked resumer"
+    );
+    // SAFETY: `data` is sent by `Checked::switch`.
+    let (target, t) = unsafe { receive::<R>(cx, data) };
+    let entry = target.entry.expect("a fresh context has no entry");
+    // SAFETY: The entry is erased from `Entry<R::Context>` in `new_on`.
+    let entry = unsafe { mem::transmute::<Entry<()>, Entry<R::Context>>(entry) };
+    let cx = t
+        .context
+        .expect("the map of a fresh context returned nothing");
+    // SAFETY: The contract is the same.
+    unsafe { entry(cx, t.data) }
+}
+
+// SAFETY: Every operation is forwarded to `R` with its contract unchanged, and
+// the map function of every switch is called on top of the target stack.
+unsafe impl<R: Resume> Resume for Checked<R> {
+    type Context = R::Context;
+
+    type NewError = R::NewError;
+
+    fn capabilities(&self) -> Capabilities {
+        self.0.capabilities()
+    }
+
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<R::Context>,
+    ) -> Result<NonNull<R::Context>, R::NewError> {
+        let start = stack.as_non_null_ptr().addr().get();
+        let end = start + stack.len();
+
+        let mut registry = registry();
+        // The contexts left on the memory are gone, e.g. those of finished
+        // contexts whose stacks are reused.
+        let mut stale = registry.split_off(&start);
+        registry.append(&mut stale.split_off(&end));
+
+        // SAFETY: The contract is the same.
+        let cx = unsafe { self.0.new_on(stack, enter::<R>) }?;
+        let info = Info {
+            backend: TypeId::of::<R>(),
+            stack: self.0.capabilities().on_stack.then_some((start, end)),
+            owner: None,
+            migratable: self.0.capabilities().migratable,
+            // SAFETY: Only the type of contexts is erased.
+            entry: Some(unsafe { mem::transmute::<Entry<R::Context>, Entry<()>>(entry) }),
+        };
+        registry.insert(cx.addr().get(), info);
+        Ok(cx)
+    }
+
+    unsafe fn resume(
+        &self,
+        cx: NonNull<R::Context>,
+        data: *mut (),
+    ) -> Transfer<R::Context> {
+        // SAFETY: The contract is the same.
+        unsafe { self.switch(cx, data, None) }
+    }
+
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<R::Context>,
+        data: *mut (),
+        map: Map<R::Context>,
+    ) -> Transfer<R::Context> {
+        // SAFETY: The contract is the same.
+        unsafe { self.switch(cx, data, Some(map)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr::{self, NonNull};
+    use std::{
+        panic::{self, AssertUnwindSafe},
+        string::String,
+        vec,
+        vec::Vec,
+    };
+
+    use super::Checked;
+    use crate::{Resume, Transfer};
+
+    fn stack() -> Vec<u8> {
+        vec![0; 4096 * 16]
+    }
+
+    unsafe extern "C" fn count<R: Resume + Default>(
+        mut cx: NonNull<R::Context>,
+        data: *mut (),
+    ) -> ! {
+        let rs = Checked(R::default());
+        let mut n = data.addr();
+        loop {
+            n += 1;
+            let t = unsafe { rs.resume(cx, ptr::without_provenance_mut(n)) };
+            cx = t.context.unwrap();
+            n = t.data.addr();
+        }
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C-unwind" fn double<C>(cx: NonNull<C>, data: *mut ()) -> Transfer<C> {
+        Transfer {
+            context: Some(cx),
+            data: ptr::without_provenance_mut(data.addr() * 2),
+        }
+    }
+
+    fn transfer<R: Resume + Default>() {
+        let rs = Checked(R::default());
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let mut cx = unsafe { rs.new_on(stack, count::<R>) }.unwrap();
+        for i in 0..100 {
+            let t = unsafe { rs.resume(cx, ptr::without_provenance_mut(i)) };
+            assert_eq!(t.data.addr(), i + 1);
+            cx = t.context.unwrap();
+        }
+        let t = unsafe { rs.resume_with(cx, ptr::without_provenance_mut(5), double) };
+        assert_eq!(t.data.addr(), 11);
+    }
+
+    /// Resumes a handle that is already consumed, which is only detected if
+    /// the backend hands out a new handle every time a context is suspended.
+    fn stale<R: Resume + Default>() {
+        let rs = Checked(R::default());
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { rs.new_on(stack, count::<R>) }.unwrap();
+        unsafe { rs.resume(cx, ptr::null_mut()) };
+        unsafe { rs.resume(cx, ptr::null_mut()) };
+    }
+
+    /// Tries to resume the context itself, which is passed as `data`.
+    unsafe extern "C" fn resume_self<R: Resume + Default>(
+        cx: NonNull<R::Context>,
+        data: *mut (),
+    ) -> ! {
+        let rs = Checked(R::default());
+        let this = NonNull::new(data.cast()).unwrap();
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+            rs.resume(this, ptr::null_mut())
+        }));
+        let Err(payload) = ret else { unreachable!() };
+        let msg = payload.downcast::<String>().unwrap();
+        unsafe { rs.resume(cx, ptr::from_ref(&*msg).cast_mut().cast()) };
+        unreachable!()
+    }
+
+    fn running<R: Resume + Default>() {
+        let rs = Checked(R::default());
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { rs.new_on(stack, resume_self::<R>) }.unwrap();
+        let t = unsafe { rs.resume(cx, cx.as_ptr().cast()) };
+        let msg = unsafe { &*t.data.cast::<String>() };
+        assert!(msg.contains("not a suspended context"), "{msg}");
+    }
+
+    fn unchecked<R: Resume + Default>() {
+        let rs = R::default();
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { rs.new_on(stack, count::<R>) }.unwrap();
+        unsafe { Checked(rs).resume(cx, ptr::null_mut()) };
+    }
+
+    #[cfg(all(feature = "boost", not(unico_boost_fallback), not(miri)))]
+    mod boost {
+        use crate::boost::Boost;
+
+        #[test]
+        fn transfer() {
+            super::transfer::<Boost>()
+        }
+
+        #[test]
+        fn running() {
+            super::running::<Boost>()
+        }
+
+        #[test]
+        #[should_panic = "not a suspended context"]
+        fn stale() {
+            super::stale::<Boost>()
+        }
+
+        #[test]
+        #[should_panic = "not a suspended context"]
+        fn unchecked() {
+            super::unchecked::<Boost>()
+        }
+    }
+
+    #[cfg(all(any(feature = "ucx", unico_boost_fallback), not(miri)))]
+    mod ucx {
+        use core::ptr::{self, NonNull};
+        use std::thread;
+
+        use super::{Checked, count, stack};
+        use crate::{Resume, ucx::Ucontext};
+
+        #[test]
+        fn transfer() {
+            super::transfer::<Ucontext>()
+        }
+
+        #[test]
+        fn running() {
+            super::running::<Ucontext>()
+        }
+
+        #[test]
+        fn wrong_thread() {
+            let rs = Checked(Ucontext);
+            let mut stack = stack();
+            let stack = NonNull::from(&mut stack[..]);
+            let cx = unsafe { rs.new_on(stack, count::<Ucontext>) }.unwrap();
+            let t = unsafe { rs.resume(cx, ptr::null_mut()) };
+            let addr = t.context.unwrap().addr();
+
+            let ret = thread::spawn(move || {
+                let cx = NonNull::without_provenance(addr);
+                unsafe { rs.resume(cx, ptr::null_mut()) };
+            })
+            .join();
+            let payload = ret.unwrap_err();
+            let msg = payload.downcast_ref::<std::string::String>().unwrap();
+            assert!(msg.contains("but resumed on"), "{msg}");
+        }
+    }
+
+    #[cfg(feature = "thread")]
+    mod thread {
+        use crate::thread::Thread;
+
+        #[test]
+        fn transfer() {
+            super::transfer::<Thread>()
+        }
+
+        #[test]
+        fn running() {
+            super::running::<Thread>()
+        }
+
+        #[test]
+        #[should_panic = "not a suspended context"]
+        fn unchecked() {
+            super::unchecked::<Thread>()
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! The safe, typed layer over [`Resume`].
+//!
+//! A [`Cx`] uniquely references a suspended context, and is consumed when
+//! resumed. Every transfer moves a value of type `T` to the target context,
+//! and the context of the resumer is handed to the target in turn, so no
+//! context can be resumed twice.
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ptr::{self, NonNull},
+};
+
+use crate::{Resume, Transfer};
+
+/// A suspended context of the resumer `R`, which receives a value of `T` when
+/// resumed.
+///
+/// The context lives on a stack borrowed for `'s`, and is not [`Send`], since
+/// a context may hold thread-local references in its stack.
+pub struct Cx<'s, R: Resume, T> {
+    rs: R,
+    cx: NonNull<R::Context>,
+    marker: PhantomData<&'s mut T>,
+}
+
+/// The data passed to a fresh context for the first time.
+struct Start<R, F> {
+    rs: R,
+    func: F,
+}
+
+impl<'s, R: Resume, T: 's> Cx<'s, R, T> {
+    /// Creates a new context on top of `stack`, which executes `func` when
+    /// resumed for the first time.
+    ///
+    /// `func` receives the resumer's context and the value passed to it, and
+    /// returns the context to be resumed next with the final value. The next
+    /// context then receives `None` as the resumer, since the current one is
+    /// finished. Likewise, `func` receives `None` if the new context is first
+    /// resumed by a finished one.
+    ///
+    /// The process is aborted if `func` panics.
+    pub fn new_on<F>(rs: R, stack: &'s mut [u8], func: F) -> Result<Self, R::NewError>
+    where
+        F: FnOnce(Option<Self>, T) -> (Self, T) + 's,
+    {
+        // SAFETY: `stack` is borrowed for `'s` and the context is only
+        // accessible through `Self`.
+        let cx = unsafe { rs.new_on(NonNull::from(stack), entry::<R, T, F>)? };
+
+        let mut start = ManuallyDrop::new(Start {
+            rs: rs.clone(),
+            func,
+        });
+        // SAFETY: `entry` moves `start` out and then yields back immediately.
+        let t = unsafe { rs.resume(cx, ptr::from_mut(&mut start).cast()) };
+        let cx = t
+            .context
+            .expect("the fresh context yielded without a context");
+        Ok(Cx {
+            rs,
+            cx,
+            marker: PhantomData,
+        })
+    }
+
+    /// Resumes the context with `value`, and returns when some context resumes
+    /// the current one.
+    ///
+    /// The returned context is that resumer, or `None` if it is finished.
+    pub fn resume(self, value: T) -> (Option<Self>, T) {
+        let Cx { rs, cx, .. } = self;
+        let mut value = ManuallyDrop::new(value);
+        // SAFETY: `cx` is uniquely owned, and waits for a value of `T`. The
+        // value is moved out by the target before the current stack is
+        // resumed.
+        let t = unsafe { rs.resume(cx, ptr::from_mut(&mut value).cast()) };
+        // SAFETY: The current context is only resumed by `Cx<R, T>`s.
+        unsafe { Self::receive(rs, t) }
+    }
+
+    /// Converts the transfer structure received by the current context.
+    ///
+    /// # Safety
+    ///
+    /// `t.data` must point to a valid value of `T`, which is moved out.
+    unsafe fn receive(rs: R, t: Transfer<R::Context>) -> (Option<Self>, T) {
+        // SAFETY: See the contract.
+        let value = unsafe { t.data.cast::<T>().read() };
+        let cx = t.context.map(|cx| Cx {
+            rs,
+            cx,
+            marker: PhantomData,
+        });
+        (cx, value)
+    }
+}
+
+unsafe extern "C" fn entry<'s, R, T, F>(cx: NonNull<R::Context>, data: *mut ()) -> !
+where
+    R: Resume,
+    T: 's,
+    F: FnOnce(Option<Cx<'s, R, T>>, T) -> (Cx<'s, R, T>, T) + 's,
+{
+    // SAFETY: `data` is passed from `Cx::new_on`, which is never read again.
+    let Start { rs, func } = unsafe { data.cast::<Start<R, F>>().read() };
+
+    // SAFETY: `cx` is the creator in `Cx::new_on`, which expects nothing.
+    let t = unsafe { rs.resume(cx, ptr::null_mut()) };
+    // SAFETY: The current context is only resumed by `Cx<R, T>`s from now on.
+    let (cx, value) = unsafe { Cx::<'s, R, T>::receive(rs.clone(), t) };
+
+    let (next, value) = func(cx, value);
+    let Cx { rs, cx, .. } = next;
+    let mut value = ManuallyDrop::new(value);
+    // SAFETY: Same as `Cx::resume`, except that the current context is
+    // discarded in `finish`.
+    unsafe { rs.resume_with(cx, ptr::from_mut(&mut value).cast(), finish) };
+    unreachable!("a finished context is resumed")
+}
+
+#[allow(improper_ctypes_definitions)]
+unsafe extern "C-unwind" fn finish<C>(_: NonNull<C>, data: *mut ()) -> Transfer<C> {
+    Transfer {
+        context: None,
+        data,
+    }
+}
+
+impl<R: Resume + fmt::Debug, T> fmt::Debug for Cx<'_, R, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cx")
+            .field("rs", &self.rs)
+            .field("cx", &self.cx)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr::NonNull;
+    use std::{
+        string::{String, ToString},
+        vec,
+        vec::Vec,
+    };
+
+    use super::Cx;
+    use crate::{DynResume, NewError, Resume};
+
+    fn stack() -> Vec<u8> {
+        vec![0; 4096 * 16]
+    }
+
+    /// Doubles every number until receiving 0.
+    fn double<R: Resume + Default>() {
+        let mut stack = stack();
+        let cx = Cx::new_on(R::default(), &mut stack, |cx, mut n: usize| {
+            let mut cx = cx.unwrap();
+            while n != 0 {
+                let (next, m) = cx.resume(n * 2);
+                (cx, n) = (next.unwrap(), m);
+            }
+            (cx, usize::MAX)
+        })
+        .unwrap();
+
+        let mut cx = Some(cx);
+        for i in 1..10 {
+            let (next, n) = cx.take().unwrap().resume(i);
+            assert_eq!(n, i * 2);
+            cx = next;
+        }
+        let (next, n) = cx.unwrap().resume(0);
+        assert!(next.is_none());
+        assert_eq!(n, usize::MAX);
+    }
+
+    enum Msg<'s, R: Resume> {
+        Text(String),
+        Cx(Cx<'s, R, Msg<'s, R>>),
+    }
+
+    /// Passes a context to another one, which resumes the former directly.
+    fn symmetric<R: Resume + Default>() {
+        let (mut s1, mut s2) = (stack(), stack());
+        let c1 = Cx::new_on(R::default(), &mut s1, |root, msg| {
+            let Msg::Cx(c2) = msg else { unreachable!() };
+            let (c2, msg) = c2.resume(Msg::Cx(root.unwrap()));
+            let Msg::Text(mut text) = msg else {
+                unreachable!()
+            };
+            text.push_str(" c1");
+            (c2.unwrap(), Msg::Text(text))
+        })
+        .unwrap();
+        let c2 = Cx::new_on(R::default(), &mut s2, |c1, msg| {
+            let Msg::Cx(root) = msg else { unreachable!() };
+            let (_, msg) = c1.unwrap().resume(Msg::Text(String::from("c2")));
+            let Msg::Text(mut text) = msg else {
+                unreachable!()
+            };
+            text.push_str(" c2");
+            (root, Msg::Text(text))
+        })
+        .unwrap();
+
+        let (next, msg) = c1.resume(Msg::Cx(c2));
+        assert!(next.is_none());
+        let Msg::Text(text) = msg else { unreachable!() };
+        assert_eq!(text, "c2 c1 c2");
+    }
+
+    /// Finishes a context by resuming a fresh one.
+    fn fresh<R: Resume + Default>() {
+        let (mut s1, mut s2) = (stack(), stack());
+        let c1 = Cx::new_on(R::default(), &mut s1, |root, msg| {
+            let Msg::Cx(c2) = msg else { unreachable!() };
+            (c2, Msg::Cx(root.unwrap()))
+        })
+        .unwrap();
+        let c2 = Cx::new_on(R::default(), &mut s2, |c1, msg| {
+            assert!(c1.is_none());
+            let Msg::Cx(root) = msg else { unreachable!() };
+            (root, Msg::Text(String::from("fresh")))
+        })
+        .unwrap();
+
+        let (next, msg) = c1.resume(Msg::Cx(c2));
+        assert!(next.is_none());
+        let Msg::Text(text) = msg else { unreachable!() };
+        assert_eq!(text, "fresh");
+    }
+
+    /// Keeps the error of the backend through [`DynResume`].
+    fn too_small<R: Resume + Default + Sync>() -> NewError
+    where
+        R::NewError: Into<NewError>,
+    {
+        unsafe extern "C" fn entry(_: NonNull<()>, _: *mut ()) -> ! {
+            unreachable!()
+        }
+
+        let mut stack = [0u8; 16];
+        let rs: &dyn DynResume = &R::default();
+        // SAFETY: The stack is valid, and is too small for any context.
+        let err = unsafe { rs.new_on(NonNull::from(&mut stack[..]), entry) };
+        let err = err.unwrap_err();
+        assert_eq!(err.to_string(), "the stack is too small for a context");
+        err
+    }
+
+    #[cfg(all(feature = "boost", not(unico_boost_fallback), not(miri)))]
+    mod boost {
+        use crate::{
+            NewError,
+            boost::{self, Boost},
+        };
+
+        #[test]
+        fn double() {
+            super::double::<Boost>()
+        }
+
+        #[test]
+        fn symmetric() {
+            super::symmetric::<Boost>()
+        }
+
+        #[test]
+        fn fresh() {
+            super::fresh::<Boost>()
+        }
+
+        #[test]
+        fn too_small() {
+            let err = super::too_small::<Boost>();
+            assert!(matches!(
+                err,
+                NewError::Boost(boost::NewError::StackTooSmall)
+            ));
+        }
+    }
+
+    #[cfg(all(any(feature = "ucx", unico_boost_fallback), not(miri)))]
+    mod ucx {
+        use crate::{
+            NewError,
+            ucx::{self, Ucontext, UcontextNoMask},
+        };
+
+        #[test]
+        fn double() {
+            super::double::<Ucontext>();
+            super::double::<UcontextNoMask>();
+        }
+
+        #[test]
+        fn symmetric() {
+            super::symmetric::<Ucontext>();
+            super::symmetric::<UcontextNoMask>();
+        }
+
+        #[test]
+        fn fresh() {
+            super::fresh::<Ucontext>();
+            super::fresh::<UcontextNoMask>();
+        }
+
+        #[test]
+        fn too_small() {
+            let err = super::too_small::<Ucontext>();
+            assert!(matches!(err, NewError::Ucx(ucx::NewError::StackTooSmall)));
+        }
+    }
+
+    #[cfg(feature = "thread")]
+    mod thread {
+        use crate::{
+            NewError,
+            thread::{self, Thread},
+        };
+
+        #[test]
+        fn double() {
+            super::double::<Thread>()
+        }
+
+        #[test]
+        fn symmetric() {
+            super::symmetric::<Thread>()
+        }
+
+        #[test]
+        fn fresh() {
+            super::fresh::<Thread>()
+        }
+
+        #[test]
+        fn too_small() {
+            let err = super::too_small::<Thread>();
+            assert!(matches!(
+                err,
+                NewError::Thread(thread::NewError::StackTooSmall)
+            ));
+        }
+    }
+}
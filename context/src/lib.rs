@@ -4,31 +4,78 @@
 #![deny(rust_2024_compatibility)]
 #![deny(trivial_casts)]
 #![deny(trivial_numeric_casts)]
-#![cfg_attr(feature = "ucx", feature(new_uninit))]
 #![allow(internal_features)]
 #![feature(allocator_api)]
 #![feature(allow_internal_unstable)]
 #![feature(slice_ptr_get)]
+#![cfg_attr(feature = "default-resumer", feature(linkage))]
 
+// `unico_boost_fallback` is set by the build script if the `boost` feature is
+// enabled but Boost doesn't support the target.
 cfg_if::cfg_if! {
-    if #[cfg(feature = "boost")] {
+    if #[cfg(all(feature = "boost", not(unico_boost_fallback)))] {
         pub mod boost;
     }
 }
 cfg_if::cfg_if! {
-    if #[cfg(feature = "ucx")] {
-        extern crate std;
+    if #[cfg(feature = "checked")] {
+        pub mod checked;
+    }
+}
+cfg_if::cfg_if! {
+    if #[cfg(feature = "native")] {
+        pub mod native;
+    }
+}
+cfg_if::cfg_if! {
+    if #[cfg(feature = "thread")] {
+        pub mod thread;
+    }
+}
+cfg_if::cfg_if! {
+    if #[cfg(any(feature = "ucx", unico_boost_fallback))] {
         pub mod ucx;
     }
 }
+mod cx;
 mod page;
 
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "boost", not(unico_boost_fallback)))] {
+        /// The preferred resumer of the `boost` feature on the current target.
+        ///
+        /// This is `boost::Boost` if Boost.Context supports the target, or
+        /// `ucx::Ucontext` otherwise. Depend on this re-export instead of `Boost`
+        /// for portability.
+        pub use self::boost::Boost as DefaultResumer;
+    } else if #[cfg(feature = "boost")] {
+        /// The preferred resumer of the `boost` feature on the current target.
+        ///
+        /// This is `boost::Boost` if Boost.Context supports the target, or
+        /// `ucx::Ucontext` otherwise. Depend on this re-export instead of `Boost`
+        /// for portability.
+        pub use self::ucx::Ucontext as DefaultResumer;
+    }
+}
+
+#[cfg(any(
+    test,
+    feature = "checked",
+    feature = "thread",
+    feature = "ucx",
+    unico_boost_fallback
+))]
+extern crate std;
+
 use core::{
     alloc::{AllocError, Layout},
-    fmt::Debug,
+    fmt::{self, Debug},
+    mem,
     ptr::NonNull,
 };
 
+pub use self::cx::Cx;
+
 /// The transfer structure between contexts.
 #[derive(Debug)]
 #[repr(C)]
@@ -45,6 +92,70 @@ pub struct Transfer<C> {
     pub data: *mut (),
 }
 
+/// The static properties of a [`Resume`] implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The minimum layout of stacks passed to [`Resume::new_on`], including
+    /// the context record if it's saved on the stack.
+    pub min_stack: Layout,
+    /// The size of the context record of a suspended context.
+    pub context_size: usize,
+    /// Whether the floating-point control and status registers are saved and
+    /// restored in each context.
+    pub preserves_fp_state: bool,
+    /// Whether the signal mask is saved and restored in each context.
+    pub preserves_signal_mask: bool,
+    /// Whether a suspended context may be resumed on another thread.
+    pub migratable: bool,
+    /// Whether contexts run on the stacks passed to [`Resume::new_on`].
+    pub on_stack: bool,
+}
+
+impl Capabilities {
+    /// Creates the capabilities of a backend whose context records of `C` are
+    /// saved on top of the stacks, with nothing preserved and no migration
+    /// allowed.
+    ///
+    /// The contexts are assumed to run on the stacks passed to them.
+    pub const fn new<C>() -> Self {
+        Capabilities {
+            min_stack: min_stack::<C>(),
+            context_size: mem::size_of::<C>(),
+            preserves_fp_state: false,
+            preserves_signal_mask: false,
+            migratable: false,
+            on_stack: true,
+        }
+    }
+
+    /// Sets whether the floating-point state is preserved.
+    pub const fn preserves_fp_state(self, preserves_fp_state: bool) -> Self {
+        Capabilities {
+            preserves_fp_state,
+            ..self
+        }
+    }
+
+    /// Sets whether the signal mask is preserved.
+    pub const fn preserves_signal_mask(self, preserves_signal_mask: bool) -> Self {
+        Capabilities {
+            preserves_signal_mask,
+            ..self
+        }
+    }
+
+    /// Sets whether contexts may migrate between threads.
+    pub const fn migratable(self, migratable: bool) -> Self {
+        Capabilities { migratable, ..self }
+    }
+
+    /// Sets whether contexts run on the stacks passed to them.
+    pub const fn on_stack(self, on_stack: bool) -> Self {
+        Capabilities { on_stack, ..self }
+    }
+}
+
 pub type Entry<C> = unsafe extern "C" fn(cx: NonNull<C>, data: *mut ()) -> !;
 #[allow(improper_ctypes_definitions)]
 pub type Map<C> =
@@ -63,6 +174,9 @@ pub unsafe trait Resume: Sized + Clone + 'static {
     /// The error type returned during creation of some context.
     type NewError: Debug;
 
+    /// The static properties of this implementation.
+    fn capabilities(&self) -> Capabilities;
+
     /// Creates a new context on top of some stack.
     ///
     /// # Safety
@@ -107,15 +221,194 @@ pub unsafe trait Resume: Sized + Clone + 'static {
     ) -> Transfer<Self::Context>;
 }
 
-fn layout_union(l1: Layout, l2: Layout) -> Layout {
-    let size = l1.size().max(l2.size());
-    let align = l1.align().max(l2.align());
-    Layout::from_size_align(size, align).unwrap()
+/// The error of creating a context with a [`DynResume`] or the global resumer,
+/// which keeps the error of the backend.
+///
+/// Implement [`Into<NewError>`] for the error of a custom resumer to erase it
+/// as well.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum NewError {
+    #[cfg(all(feature = "boost", not(unico_boost_fallback)))]
+    Boost(boost::NewError),
+    #[cfg(feature = "native")]
+    Native(native::NewError),
+    #[cfg(feature = "thread")]
+    Thread(thread::NewError),
+    #[cfg(any(feature = "ucx", unico_boost_fallback))]
+    Ucx(ucx::NewError),
+    /// The context failed to be created by some other resumer.
+    Other(AllocError),
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(all(feature = "boost", not(unico_boost_fallback)))]
+            NewError::Boost(err) => fmt::Display::fmt(err, f),
+            #[cfg(feature = "native")]
+            NewError::Native(err) => fmt::Display::fmt(err, f),
+            #[cfg(feature = "thread")]
+            NewError::Thread(err) => fmt::Display::fmt(err, f),
+            #[cfg(any(feature = "ucx", unico_boost_fallback))]
+            NewError::Ucx(err) => fmt::Display::fmt(err, f),
+            NewError::Other(err) => fmt::Display::fmt(err, f),
+        }
+    }
+}
+
+impl core::error::Error for NewError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(all(feature = "boost", not(unico_boost_fallback)))]
+            NewError::Boost(err) => err.source(),
+            #[cfg(feature = "native")]
+            NewError::Native(err) => err.source(),
+            #[cfg(feature = "thread")]
+            NewError::Thread(err) => err.source(),
+            #[cfg(any(feature = "ucx", unico_boost_fallback))]
+            NewError::Ucx(err) => err.source(),
+            NewError::Other(err) => err.source(),
+        }
+    }
+}
+
+impl From<AllocError> for NewError {
+    fn from(err: AllocError) -> Self {
+        NewError::Other(err)
+    }
+}
+
+/// The object-safe version of [`Resume`], with the type of contexts erased.
+///
+/// Every [`Resume`] implementation that is [`Sync`] implements this trait, so
+/// that a resumer can be selected at runtime with `&'static dyn DynResume`,
+/// as long as its error is convertible into [`NewError`].
+///
+/// # Safety
+///
+/// See [`Resume`] for more information.
+pub unsafe trait DynResume: Sync + 'static {
+    /// The static properties of this implementation.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Creates a new context on top of some stack.
+    ///
+    /// # Safety
+    ///
+    /// See [`Resume::new_on`] for more information.
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<()>,
+    ) -> Result<NonNull<()>, NewError>;
+
+    /// Yields the execution to the target context 'cx' with `data` passed to
+    /// the destination.
+    ///
+    /// # Safety
+    ///
+    /// See [`Resume::resume`] for more information.
+    unsafe fn resume(&self, cx: NonNull<()>, data: *mut ()) -> Transfer<()>;
+
+    /// Yields the execution to the target context 'cx' with `data` passed to
+    /// the destination, and executes a function on top of that stack.
+    ///
+    /// # Safety
+    ///
+    /// See [`Resume::resume_with`] for more information.
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<()>,
+        data: *mut (),
+        map: Map<()>,
+    ) -> Transfer<()>;
+}
+
+// SAFETY: The type of contexts is only erased, and the layout of function
+// pointers and transfer structures are the same regardless of `R::Context`.
+unsafe impl<R: Resume + Sync> DynResume for R
+where
+    R::NewError: Into<NewError>,
+{
+    fn capabilities(&self) -> Capabilities {
+        Resume::capabilities(self)
+    }
+
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<()>,
+    ) -> Result<NonNull<()>, NewError> {
+        // SAFETY: The contract is the same.
+        unsafe {
+            let entry = mem::transmute::<Entry<()>, Entry<R::Context>>(entry);
+            Resume::new_on(self, stack, entry)
+        }
+        .map(NonNull::cast)
+        .map_err(Into::into)
+    }
+
+    unsafe fn resume(&self, cx: NonNull<()>, data: *mut ()) -> Transfer<()> {
+        // SAFETY: The contract is the same.
+        unsafe { Resume::resume(self, cx.cast(), data) }.erase()
+    }
+
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<()>,
+        data: *mut (),
+        map: Map<()>,
+    ) -> Transfer<()> {
+        // SAFETY: The contract is the same.
+        unsafe {
+            let map = mem::transmute::<Map<()>, Map<R::Context>>(map);
+            Resume::resume_with(self, cx.cast(), data, map)
+        }
+        .erase()
+    }
+}
+
+impl Debug for dyn DynResume {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynResume").finish_non_exhaustive()
+    }
+}
+
+impl<C> Transfer<C> {
+    fn erase(self) -> Transfer<()> {
+        Transfer {
+            context: self.context.map(NonNull::cast),
+            data: self.data,
+        }
+    }
+}
+
+const fn layout_union(l1: Layout, l2: Layout) -> Layout {
+    let size = if l1.size() > l2.size() {
+        l1.size()
+    } else {
+        l2.size()
+    };
+    let align = if l1.align() > l2.align() {
+        l1.align()
+    } else {
+        l2.align()
+    };
+    match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid layout union"),
+    }
+}
+
+/// The minimum layout of stacks with a `T` on top of them.
+const fn min_stack<T>() -> Layout {
+    layout_union(Layout::new::<T>(), page::STACK_LAYOUT)
 }
 
 fn stack_top<T>(stack: NonNull<[u8]>) -> Option<NonNull<T>> {
     let layout = Layout::new::<T>();
-    if stack.len() < layout_union(layout, page::STACK_LAYOUT).size() {
+    if stack.len() < min_stack::<T>().size() {
         return None;
     }
 
@@ -130,11 +423,13 @@ fn stack_top<T>(stack: NonNull<[u8]>) -> Option<NonNull<T>> {
 
 // SAFETY: These functions are implemented by `global_resumer!`.
 unsafe extern "Rust" {
+    fn __rust_unico_context_capabilities() -> Capabilities;
+
     fn __rust_unico_context_new(
         stack: NonNull<u8>,
         stack_size: usize,
         entry: Entry<()>,
-    ) -> Result<NonNull<()>, AllocError>;
+    ) -> Result<NonNull<()>, NewError>;
 
     fn __rust_unico_context_resume(cx: NonNull<()>, data: *mut ()) -> Transfer<()>;
 
@@ -145,6 +440,12 @@ unsafe extern "Rust" {
     ) -> Transfer<()>;
 }
 
+/// The static properties of the global resumer.
+pub fn capabilities() -> Capabilities {
+    // SAFETY: The function has no safety requirements.
+    unsafe { __rust_unico_context_capabilities() }
+}
+
 /// Creates a new context on top of some stack.
 ///
 /// # Safety
@@ -154,7 +455,7 @@ unsafe extern "Rust" {
 pub unsafe fn new_on(
     stack: NonNull<[u8]>,
     entry: Entry<()>,
-) -> Result<NonNull<()>, AllocError> {
+) -> Result<NonNull<()>, NewError> {
     unsafe { __rust_unico_context_new(stack.as_non_null_ptr(), stack.len(), entry) }
 }
 
@@ -182,23 +483,86 @@ pub unsafe fn resume_with(cx: NonNull<()>, data: *mut (), map: Map<()>) -> Trans
     unsafe { __rust_unico_context_resume_with(cx, data, map) }
 }
 
+/// The global resumer interface (not implementation). The user should define a
+/// unique instance of that implementation using [`global_resumer`].
+///
+/// All the context-switching functions of this structure are the same as
+/// those global functions, like [`resume`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+// SAFETY: The contract is the same as the global resumer's.
+unsafe impl Resume for Global {
+    type Context = ();
+
+    type NewError = NewError;
+
+    fn capabilities(&self) -> Capabilities {
+        capabilities()
+    }
+
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<()>,
+    ) -> Result<NonNull<()>, NewError> {
+        // SAFETY: The contract is the same.
+        unsafe { new_on(stack, entry) }
+    }
+
+    unsafe fn resume(&self, cx: NonNull<()>, data: *mut ()) -> Transfer<()> {
+        // SAFETY: The contract is the same.
+        unsafe { resume(cx, data) }
+    }
+
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<()>,
+        data: *mut (),
+        map: Map<()>,
+    ) -> Transfer<()> {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, data, map) }
+    }
+}
+
 /// Define a global resumer so that those global functions (like [`resume`]) can
 /// be used in general.
 ///
 /// This macro works just like `#[global_allocator]` attribute, except it only
 /// receives the path of the target static variable, while the actual definition
-/// can lie elsewhere.
+/// can lie elsewhere. A constant expression is accepted as well, which is
+/// evaluated in every call to the resumer:
+///
+/// ```
+/// # #[cfg(all(feature = "boost", feature = "checked", not(miri)))] {
+/// use unico_context::{DefaultResumer, checked::Checked};
+///
+/// unico_context::global_resumer!(Checked(DefaultResumer));
+/// # }
+/// ```
+///
+/// If the `default-resumer` feature is enabled, [`DefaultResumer`] is used as a
+/// fallback when this macro is not called anywhere.
 #[macro_export]
 #[allow_internal_unstable(allocator_api)]
 macro_rules! global_resumer {
-    ($t:path) => {
+    (@attrs [$($attr:meta),*] $t:expr) => {
         #[unsafe(no_mangle)]
         #[doc(hidden)]
+        $(#[$attr])*
+        fn __rust_unico_context_capabilities() -> $crate::Capabilities {
+            $crate::Resume::capabilities(&$t)
+        }
+
+        #[unsafe(no_mangle)]
+        #[doc(hidden)]
+        $(#[$attr])*
         unsafe fn __rust_unico_context_new(
             stack: core::ptr::NonNull<u8>,
             stack_size: usize,
             entry: $crate::Entry<()>,
-        ) -> Result<core::ptr::NonNull<()>, core::alloc::AllocError> {
+        ) -> Result<core::ptr::NonNull<()>, $crate::NewError> {
             unsafe {
                 $crate::Resume::new_on(
                     &$t,
@@ -207,11 +571,12 @@ macro_rules! global_resumer {
                 )
             }
             .map(core::ptr::NonNull::cast)
-            .map_err(|_| core::alloc::AllocError)
+            .map_err(core::convert::Into::into)
         }
 
         #[unsafe(no_mangle)]
         #[doc(hidden)]
+        $(#[$attr])*
         unsafe fn __rust_unico_context_resume(
             cx: core::ptr::NonNull<()>,
             data: *mut (),
@@ -224,6 +589,7 @@ macro_rules! global_resumer {
 
         #[unsafe(no_mangle)]
         #[doc(hidden)]
+        $(#[$attr])*
         unsafe fn __rust_unico_context_resume_with(
             cx: core::ptr::NonNull<()>,
             data: *mut (),
@@ -239,4 +605,15 @@ macro_rules! global_resumer {
             }
         }
     };
+    ($t:expr) => {
+        $crate::global_resumer!(@attrs [] $t);
+    };
+}
+
+#[cfg(feature = "default-resumer")]
+#[allow(clippy::missing_transmute_annotations)]
+mod default_resumer {
+    // The weak definitions are overridden by the user's `global_resumer!`, if
+    // any.
+    global_resumer!(@attrs [linkage = "weak"] crate::DefaultResumer);
 }
@@ -0,0 +1,271 @@
+//! A context-switching backend written in pure Rust with naked functions.
+//!
+//! Unlike [`Boost`](crate::boost::Boost), this backend requires no C toolchain
+//! and no build script. Only x86_64 System V and aarch64 AAPCS targets are
+//! supported for now.
+
+use core::{fmt, ptr::NonNull};
+
+use crate::{Capabilities, Entry, Map, Resume, stack_top};
+
+cfg_if::cfg_if! {
+    if #[cfg(all(target_arch = "x86_64", not(windows)))] {
+        mod x86_64;
+        use self::x86_64 as arch;
+    } else if #[cfg(all(target_arch = "aarch64", not(windows)))] {
+        mod aarch64;
+        use self::aarch64 as arch;
+    } else {
+        compile_error!("The native backend does not support the current target.");
+    }
+}
+
+const CONTEXT_LEN: usize = arch::CONTEXT_SIZE / size_of::<usize>();
+
+/// The register record saved on top of a suspended stack.
+#[derive(Debug)]
+#[repr(transparent)]
+pub struct Ncx(#[allow(dead_code)] [usize; CONTEXT_LEN]);
+
+pub type Transfer = crate::Transfer<Ncx>;
+
+/// The [`Resume`] implementation with naked functions written in Rust.
+///
+/// Besides the callee-saved registers, the floating-point control and status
+/// registers (MXCSR and the x87 control word on x86_64, FPCR on aarch64) are
+/// preserved in each context, so that a context changing its rounding mode
+/// won't affect others.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Native;
+
+/// Like [`Native`], but only switches the registers required by the calling
+/// convention.
+///
+/// The floating-point control and status registers are shared among all the
+/// contexts on the same thread, i.e. a rounding mode set in one context stays
+/// in effect after switching to another. This is slightly cheaper if none of
+/// the contexts touch those registers.
+///
+/// Contexts created by this backend must not be resumed by [`Native`], and vice
+/// versa.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct NativeInt;
+
+#[derive(Debug)]
+pub enum NewError {
+    StackTooSmall,
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::StackTooSmall => {
+                f.write_str("the stack is too small for a context")
+            }
+        }
+    }
+}
+
+impl core::error::Error for NewError {}
+
+impl From<NewError> for crate::NewError {
+    fn from(err: NewError) -> Self {
+        crate::NewError::Native(err)
+    }
+}
+
+macro_rules! impl_resume {
+    ($name:ident, $resume:ident, $resume_with:ident, $fp:literal) => {
+        // SAFETY: `Ncx` is created from `stack`. See the architecture-specific
+        // modules for more information.
+        unsafe impl Resume for $name {
+            type Context = Ncx;
+
+            type NewError = NewError;
+
+            fn capabilities(&self) -> Capabilities {
+                Capabilities::new::<Ncx>()
+                    .preserves_fp_state($fp)
+                    .migratable(true)
+            }
+
+            unsafe fn new_on(
+                &self,
+                stack: NonNull<[u8]>,
+                entry: Entry<Ncx>,
+            ) -> Result<NonNull<Ncx>, NewError> {
+                let top: NonNull<()> = stack_top(stack).ok_or(NewError::StackTooSmall)?;
+                // SAFETY: The stack is valid by contract, and `stack_top` ensures
+                // that it is larger than the context record.
+                Ok(unsafe { arch::new_on(top, entry) })
+            }
+
+            #[inline]
+            unsafe fn resume(&self, cx: NonNull<Ncx>, data: *mut ()) -> Transfer {
+                // SAFETY: `cx` is valid by contract.
+                unsafe { arch::$resume(cx, data) }
+            }
+
+            #[inline]
+            unsafe fn resume_with(
+                &self,
+                cx: NonNull<Ncx>,
+                data: *mut (),
+                map: Map<Ncx>,
+            ) -> Transfer {
+                // SAFETY: `cx` and `map` is valid by contract.
+                unsafe { arch::$resume_with(cx, data, map) }
+            }
+        }
+    };
+}
+impl_resume!(Native, resume, resume_with, true);
+impl_resume!(NativeInt, resume_int, resume_with_int, false);
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use core::ptr::{self, NonNull};
+    use std::{vec, vec::Vec};
+
+    use super::{Native, NativeInt, Ncx, Transfer};
+    use crate::Resume;
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            // The rounding control bits of MXCSR.
+            const ROUNDING: u32 = 0b11 << 13;
+            const UPWARD: u32 = 0b10 << 13;
+
+            fn rounding() -> u32 {
+                let mut mxcsr = 0u32;
+                unsafe { core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr) };
+                mxcsr & ROUNDING
+            }
+
+            fn set_rounding(mode: u32) {
+                let mut mxcsr = 0u32;
+                unsafe {
+                    core::arch::asm!("stmxcsr [{}]", in(reg) &mut mxcsr);
+                    mxcsr = (mxcsr & !ROUNDING) | mode;
+                    core::arch::asm!("ldmxcsr [{}]", in(reg) &mxcsr);
+                }
+            }
+        } else {
+            // The RMode bits of FPCR.
+            const ROUNDING: u64 = 0b11 << 22;
+            const UPWARD: u64 = 0b01 << 22;
+
+            fn rounding() -> u64 {
+                let fpcr: u64;
+                unsafe { core::arch::asm!("mrs {}, fpcr", out(reg) fpcr) };
+                fpcr & ROUNDING
+            }
+
+            fn set_rounding(mode: u64) {
+                unsafe {
+                    let fpcr: u64;
+                    core::arch::asm!("mrs {}, fpcr", out(reg) fpcr);
+                    core::arch::asm!("msr fpcr, {}", in(reg) (fpcr & !ROUNDING) | mode);
+                }
+            }
+        }
+    }
+
+    fn stack() -> Vec<u8> {
+        vec![0; 4096 * 4]
+    }
+
+    unsafe extern "C" fn count(mut cx: NonNull<Ncx>, data: *mut ()) -> ! {
+        let mut n = data.addr();
+        loop {
+            n += 1;
+            let t = unsafe { Native.resume(cx, ptr::without_provenance_mut(n)) };
+            cx = t.context.unwrap();
+            n = t.data.addr();
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let mut cx = unsafe { Native.new_on(stack, count) }.unwrap();
+        for i in 0..100 {
+            let t = unsafe { Native.resume(cx, ptr::without_provenance_mut(i)) };
+            assert_eq!(t.data.addr(), i + 1);
+            cx = t.context.unwrap();
+        }
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C-unwind" fn double(cx: NonNull<Ncx>, data: *mut ()) -> Transfer {
+        Transfer {
+            context: Some(cx),
+            data: ptr::without_provenance_mut(data.addr() * 2),
+        }
+    }
+
+    #[test]
+    fn map() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { Native.new_on(stack, count) }.unwrap();
+        // `map` runs on top of the fresh stack before `count` starts.
+        let t = unsafe { Native.resume_with(cx, ptr::without_provenance_mut(3), double) };
+        assert_eq!(t.data.addr(), 7);
+        // ... and on top of a suspended stack as well.
+        let cx = t.context.unwrap();
+        let t = unsafe { Native.resume_with(cx, ptr::without_provenance_mut(5), double) };
+        assert_eq!(t.data.addr(), 11);
+    }
+
+    /// Sets the rounding mode upward, and reports whether the mode is kept
+    /// after every resumption.
+    unsafe extern "C" fn round_upward<R: Resume<Context = Ncx> + Default>(
+        mut cx: NonNull<Ncx>,
+        _: *mut (),
+    ) -> ! {
+        set_rounding(UPWARD);
+        let mut kept = true;
+        loop {
+            let t = unsafe {
+                R::default().resume(cx, ptr::without_provenance_mut(kept as _))
+            };
+            cx = t.context.unwrap();
+            kept = rounding() == UPWARD;
+        }
+    }
+
+    #[test]
+    fn rounding_isolated() {
+        let before = rounding();
+        assert_ne!(before, UPWARD);
+
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { Native.new_on(stack, round_upward::<Native>) }.unwrap();
+        let t = unsafe { Native.resume(cx, ptr::null_mut()) };
+        assert_eq!(rounding(), before);
+
+        let t = unsafe { Native.resume(t.context.unwrap(), ptr::null_mut()) };
+        assert_eq!(t.data.addr(), 1);
+        assert_eq!(rounding(), before);
+    }
+
+    #[test]
+    fn rounding_shared() {
+        let before = rounding();
+        assert_ne!(before, UPWARD);
+
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { NativeInt.new_on(stack, round_upward::<NativeInt>) }.unwrap();
+        let t = unsafe { NativeInt.resume(cx, ptr::null_mut()) };
+        assert_eq!(rounding(), UPWARD);
+
+        set_rounding(before);
+        let t = unsafe { NativeInt.resume(t.context.unwrap(), ptr::null_mut()) };
+        assert_eq!(t.data.addr(), 0);
+        set_rounding(before);
+    }
+}
@@ -0,0 +1,177 @@
+//! The aarch64 AAPCS implementation.
+//!
+//! The layout of the context record, pointed to by the saved stack pointer:
+//!
+//! ```text
+//! | 0x00    | 0x40     | 0x90      | 0xa0 | 0xa8 |
+//! | D8..D15 | X19..X28 | FP and LR | PC   | FPCR |
+//! ```
+
+use core::{
+    arch::{asm, naked_asm},
+    ptr::NonNull,
+};
+
+use super::{Ncx, Transfer};
+use crate::{Entry, Map};
+
+pub const CONTEXT_SIZE: usize = 0xb0;
+
+/// # Safety
+///
+/// `top` must be the top of a valid stack larger than [`CONTEXT_SIZE`].
+pub unsafe fn new_on(top: NonNull<()>, entry: Entry<Ncx>) -> NonNull<Ncx> {
+    let cx = top.map_addr(|addr| {
+        // On the entry of `trampoline`: SP % 16 == 0.
+        ((addr.get() & !0xf) - CONTEXT_SIZE).try_into().unwrap()
+    });
+    let slots = cx.cast::<usize>().as_ptr();
+    // SAFETY: The context record lies inside the stack by contract.
+    unsafe {
+        slots.write_bytes(0, CONTEXT_SIZE / size_of::<usize>());
+        // Inherit the floating-point control register from the creator.
+        asm!(
+            "mrs {0}, fpcr",
+            "str {0}, [{1}, #0xa8]",
+            out(reg) _,
+            in(reg) slots,
+            options(nostack, preserves_flags),
+        );
+        // X19 holds `entry`.
+        slots.add(8).write(entry as usize);
+        // `map` functions return to LR, and `resume` jumps to PC.
+        slots.add(19).write(trampoline as *const () as usize);
+        slots.add(20).write(trampoline as *const () as usize);
+    }
+    cx.cast()
+}
+
+/// The first code executed on a new context, whose transfer structure is
+/// received in X0 and X1, either from `resume` or from some `map` function.
+#[unsafe(naked)]
+unsafe extern "C" fn trampoline() -> ! {
+    naked_asm!("blr x19", "brk #0x1")
+}
+
+macro_rules! switch {
+    (
+        $(#[$attr:meta])*
+        resume: $resume:ident,
+        resume_with: $resume_with:ident,
+        save: [$($save:literal),*],
+        restore: [$($restore:literal),*] $(,)?
+    ) => {
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// See [`Resume::resume`](crate::Resume::resume) for more information.
+        #[unsafe(naked)]
+        pub unsafe extern "C" fn $resume(target: NonNull<Ncx>, data: *mut ()) -> Transfer {
+            naked_asm!(
+                // Save the current context.
+                "sub sp, sp, #0xb0",
+                "stp d8, d9, [sp, #0x00]",
+                "stp d10, d11, [sp, #0x10]",
+                "stp d12, d13, [sp, #0x20]",
+                "stp d14, d15, [sp, #0x30]",
+                "stp x19, x20, [sp, #0x40]",
+                "stp x21, x22, [sp, #0x50]",
+                "stp x23, x24, [sp, #0x60]",
+                "stp x25, x26, [sp, #0x70]",
+                "stp x27, x28, [sp, #0x80]",
+                "stp x29, x30, [sp, #0x90]",
+                "str x30, [sp, #0xa0]",
+                $($save,)*
+                // Switch the stack.
+                "mov x4, sp",
+                "mov sp, x0",
+                // Restore the target context.
+                $($restore,)*
+                "ldp d8, d9, [sp, #0x00]",
+                "ldp d10, d11, [sp, #0x10]",
+                "ldp d12, d13, [sp, #0x20]",
+                "ldp d14, d15, [sp, #0x30]",
+                "ldp x19, x20, [sp, #0x40]",
+                "ldp x21, x22, [sp, #0x50]",
+                "ldp x23, x24, [sp, #0x60]",
+                "ldp x25, x26, [sp, #0x70]",
+                "ldp x27, x28, [sp, #0x80]",
+                "ldp x29, x30, [sp, #0x90]",
+                // Return the transfer structure in X0 and X1.
+                "mov x0, x4",
+                "ldr x4, [sp, #0xa0]",
+                "add sp, sp, #0xb0",
+                "ret x4",
+            )
+        }
+
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// See [`Resume::resume_with`](crate::Resume::resume_with) for more
+        /// information.
+        #[unsafe(naked)]
+        pub unsafe extern "C" fn $resume_with(
+            target: NonNull<Ncx>,
+            data: *mut (),
+            map: Map<Ncx>,
+        ) -> Transfer {
+            naked_asm!(
+                // Save the current context.
+                "sub sp, sp, #0xb0",
+                "stp d8, d9, [sp, #0x00]",
+                "stp d10, d11, [sp, #0x10]",
+                "stp d12, d13, [sp, #0x20]",
+                "stp d14, d15, [sp, #0x30]",
+                "stp x19, x20, [sp, #0x40]",
+                "stp x21, x22, [sp, #0x50]",
+                "stp x23, x24, [sp, #0x60]",
+                "stp x25, x26, [sp, #0x70]",
+                "stp x27, x28, [sp, #0x80]",
+                "stp x29, x30, [sp, #0x90]",
+                "str x30, [sp, #0xa0]",
+                $($save,)*
+                // Switch the stack.
+                "mov x4, sp",
+                "mov sp, x0",
+                // Restore the target context.
+                $($restore,)*
+                "ldp d8, d9, [sp, #0x00]",
+                "ldp d10, d11, [sp, #0x10]",
+                "ldp d12, d13, [sp, #0x20]",
+                "ldp d14, d15, [sp, #0x30]",
+                "ldp x19, x20, [sp, #0x40]",
+                "ldp x21, x22, [sp, #0x50]",
+                "ldp x23, x24, [sp, #0x60]",
+                "ldp x25, x26, [sp, #0x70]",
+                "ldp x27, x28, [sp, #0x80]",
+                "ldp x29, x30, [sp, #0x90]",
+                // Skip PC and let `map` return its transfer structure to LR.
+                "mov x0, x4",
+                "add sp, sp, #0xb0",
+                "ret x2",
+            )
+        }
+    };
+}
+
+switch! {
+    /// Switches the context, including FPCR.
+    resume: resume,
+    resume_with: resume_with,
+    save: ["mrs x9, fpcr", "str x9, [sp, #0xa8]"],
+    restore: ["ldr x9, [sp, #0xa8]", "msr fpcr, x9"],
+}
+
+switch! {
+    /// Switches the context, excluding FPCR.
+    ///
+    /// Note that the callee-saved registers D8 to D15 are still preserved
+    /// because the calling convention requires so.
+    resume: resume_int,
+    resume_with: resume_with_int,
+    save: [],
+    restore: [],
+}
@@ -0,0 +1,159 @@
+//! The x86_64 System V implementation.
+//!
+//! The layout of the context record, pointed to by the saved stack pointer:
+//!
+//! ```text
+//! | 0x00  | 0x04   | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 |
+//! | MXCSR | x87 CW | R12  | R13  | R14  | R15  | RBX  | RBP  | RIP  |
+//! ```
+
+use core::{
+    arch::{asm, naked_asm},
+    ptr::NonNull,
+};
+
+use super::{Ncx, Transfer};
+use crate::{Entry, Map};
+
+pub const CONTEXT_SIZE: usize = 0x40;
+
+/// # Safety
+///
+/// `top` must be the top of a valid stack larger than [`CONTEXT_SIZE`].
+pub unsafe fn new_on(top: NonNull<()>, entry: Entry<Ncx>) -> NonNull<Ncx> {
+    let cx = top.map_addr(|addr| {
+        // On the entry of `trampoline`: RSP % 16 == 0.
+        ((addr.get() & !0xf) - CONTEXT_SIZE).try_into().unwrap()
+    });
+    let slots = cx.cast::<usize>().as_ptr();
+    // SAFETY: The context record lies inside the stack by contract.
+    unsafe {
+        slots.write_bytes(0, CONTEXT_SIZE / size_of::<usize>());
+        // Inherit the floating-point control words from the creator.
+        asm!(
+            "stmxcsr [{0}]",
+            "fnstcw [{0} + 0x4]",
+            in(reg) slots,
+            options(nostack, preserves_flags),
+        );
+        slots.add(5).write(entry as usize);
+        slots.add(7).write(trampoline as *const () as usize);
+    }
+    cx.cast()
+}
+
+/// The first code executed on a new context, whose transfer structure is
+/// received in RAX and RDX, either from `resume` or from some `map` function.
+#[unsafe(naked)]
+unsafe extern "C" fn trampoline() -> ! {
+    naked_asm!(
+        "mov rdi, rax",
+        "mov rsi, rdx",
+        // `entry` is stored in RBX.
+        "call rbx",
+        "ud2",
+    )
+}
+
+macro_rules! switch {
+    (
+        $(#[$attr:meta])*
+        resume: $resume:ident,
+        resume_with: $resume_with:ident,
+        save: [$($save:literal),*],
+        restore: [$($restore:literal),*] $(,)?
+    ) => {
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// See [`Resume::resume`](crate::Resume::resume) for more information.
+        #[unsafe(naked)]
+        pub unsafe extern "C" fn $resume(target: NonNull<Ncx>, data: *mut ()) -> Transfer {
+            naked_asm!(
+                // Save the current context.
+                "lea rsp, [rsp - 0x38]",
+                $($save,)*
+                "mov [rsp + 0x08], r12",
+                "mov [rsp + 0x10], r13",
+                "mov [rsp + 0x18], r14",
+                "mov [rsp + 0x20], r15",
+                "mov [rsp + 0x28], rbx",
+                "mov [rsp + 0x30], rbp",
+                // Switch the stack.
+                "mov rax, rsp",
+                "mov rsp, rdi",
+                // Restore the target context.
+                "mov r8, [rsp + 0x38]",
+                $($restore,)*
+                "mov r12, [rsp + 0x08]",
+                "mov r13, [rsp + 0x10]",
+                "mov r14, [rsp + 0x18]",
+                "mov r15, [rsp + 0x20]",
+                "mov rbx, [rsp + 0x28]",
+                "mov rbp, [rsp + 0x30]",
+                "lea rsp, [rsp + 0x40]",
+                // Return the transfer structure in RAX and RDX.
+                "mov rdx, rsi",
+                "jmp r8",
+            )
+        }
+
+        $(#[$attr])*
+        ///
+        /// # Safety
+        ///
+        /// See [`Resume::resume_with`](crate::Resume::resume_with) for more
+        /// information.
+        #[unsafe(naked)]
+        pub unsafe extern "C" fn $resume_with(
+            target: NonNull<Ncx>,
+            data: *mut (),
+            map: Map<Ncx>,
+        ) -> Transfer {
+            naked_asm!(
+                // Save the current context.
+                "lea rsp, [rsp - 0x38]",
+                $($save,)*
+                "mov [rsp + 0x08], r12",
+                "mov [rsp + 0x10], r13",
+                "mov [rsp + 0x18], r14",
+                "mov [rsp + 0x20], r15",
+                "mov [rsp + 0x28], rbx",
+                "mov [rsp + 0x30], rbp",
+                // Switch the stack.
+                "mov rax, rsp",
+                "mov rsp, rdi",
+                // Restore the target context.
+                $($restore,)*
+                "mov r12, [rsp + 0x08]",
+                "mov r13, [rsp + 0x10]",
+                "mov r14, [rsp + 0x18]",
+                "mov r15, [rsp + 0x20]",
+                "mov rbx, [rsp + 0x28]",
+                "mov rbp, [rsp + 0x30]",
+                // Keep the return address on the stack, so that `map` returns its
+                // transfer structure to the target context.
+                "lea rsp, [rsp + 0x38]",
+                "mov rdi, rax",
+                "jmp rdx",
+            )
+        }
+    };
+}
+
+switch! {
+    /// Switches the context, including MXCSR and the x87 control word.
+    resume: resume,
+    resume_with: resume_with,
+    save: ["stmxcsr [rsp]", "fnstcw [rsp + 0x4]"],
+    restore: ["ldmxcsr [rsp]", "fldcw [rsp + 0x4]"],
+}
+
+switch! {
+    /// Switches the context, excluding any floating-point state.
+    resume: resume_int,
+    resume_with: resume_with_int,
+    save: [],
+    restore: [],
+}
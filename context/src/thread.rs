@@ -0,0 +1,304 @@
+//! A context-switching backend emulated with OS threads.
+//!
+//! Every context runs on a thread of its own, and only one of the contexts is
+//! running at a time while the others are parked. This is much slower than
+//! other backends, but involves no assembly or foreign functions, so that
+//! interpreters like Miri can run the code on top of it.
+//!
+//! The thread-local storage is not shared between contexts, and values passed
+//! between contexts actually move between threads, so values that are not
+//! [`Send`] (like lock guards) must not be passed across a switch. The threads
+//! of finished contexts are parked forever. Both the threads and the records
+//! are leaked, so this backend is meant for testing only.
+//!
+//! Model checkers like loom are not supported. They require all the threads to
+//! finish, while a context never returns from its entry to let its thread be
+//! joined, and the hand-off would have to use their primitives instead of the
+//! ones from `std`.
+
+use core::{cell::Cell, fmt, ptr::NonNull};
+use std::{
+    boxed::Box,
+    io::Error as IoError,
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+use crate::{Capabilities, Entry, Map, Resume, min_stack};
+
+pub type Transfer = crate::Transfer<Tcx>;
+
+/// A switch to some context, sent by its resumer.
+struct Handoff {
+    from: NonNull<Tcx>,
+    data: *mut (),
+    map: Option<Map<Tcx>>,
+}
+
+// SAFETY: The handoff is received by exactly one context, and the data is only
+// accessed by one context at a time.
+unsafe impl Send for Handoff {}
+
+/// The context record of [`Thread`], which parks its thread until switched to.
+pub struct Tcx {
+    handoff: Mutex<Option<Handoff>>,
+    cond: Condvar,
+}
+
+impl Tcx {
+    fn new() -> NonNull<Tcx> {
+        let tcx = Box::new(Tcx {
+            handoff: Mutex::new(None),
+            cond: Condvar::new(),
+        });
+        NonNull::from(Box::leak(tcx))
+    }
+
+    fn send(&self, handoff: Handoff) {
+        let mut slot = self.handoff.lock().unwrap();
+        assert!(slot.is_none(), "the context is resumed twice");
+        *slot = Some(handoff);
+        self.cond.notify_one();
+    }
+
+    fn wait(&self) -> Handoff {
+        let mut slot = self.handoff.lock().unwrap();
+        loop {
+            match slot.take() {
+                Some(handoff) => break handoff,
+                None => slot = self.cond.wait(slot).unwrap(),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Tcx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tcx").finish_non_exhaustive()
+    }
+}
+
+std::thread_local! {
+    /// The record of the context running on the current thread, or `None` if
+    /// the thread is not spawned by `new_on` and has never switched.
+    static CURRENT: Cell<Option<NonNull<Tcx>>> = const { Cell::new(None) };
+}
+
+fn current() -> NonNull<Tcx> {
+    CURRENT.get().unwrap_or_else(|| {
+        let root = Tcx::new();
+        CURRENT.set(Some(root));
+        root
+    })
+}
+
+/// Receives the transfer structure after switched to, executing the map
+/// function on the current thread if any.
+///
+/// # Safety
+///
+/// The handoff must be sent by [`resume_with`].
+unsafe fn received(handoff: Handoff) -> Transfer {
+    let Handoff { from, data, map } = handoff;
+    match map {
+        // SAFETY: `map` is valid by the contract of `resume_with`.
+        Some(map) => unsafe { map(from, data) },
+        None => Transfer {
+            context: Some(from),
+            data,
+        },
+    }
+}
+
+/// # Safety
+///
+/// See [`Resume::resume_with`] for more information.
+unsafe fn resume_with(
+    target: NonNull<Tcx>,
+    data: *mut (),
+    map: Option<Map<Tcx>>,
+) -> Transfer {
+    let current = current();
+    let handoff = Handoff {
+        from: current,
+        data,
+        map,
+    };
+    // SAFETY: Records are never deallocated.
+    unsafe { target.as_ref() }.send(handoff);
+    // SAFETY: Same as above.
+    let handoff = unsafe { current.as_ref() }.wait();
+    // SAFETY: The handoff is sent by some other context in `resume_with`.
+    unsafe { received(handoff) }
+}
+
+/// A pointer sent to a newly spawned thread.
+struct Start {
+    tcx: NonNull<Tcx>,
+    entry: Entry<Tcx>,
+}
+
+// SAFETY: The record is not accessed by the spawner any longer.
+unsafe impl Send for Start {}
+
+impl Start {
+    fn run(self) -> ! {
+        CURRENT.set(Some(self.tcx));
+        // SAFETY: Records are never deallocated.
+        let handoff = unsafe { self.tcx.as_ref() }.wait();
+        // SAFETY: The handoff is sent by some other context in `resume_with`.
+        let t = unsafe { received(handoff) };
+        let cx = t
+            .context
+            .expect("the map of a fresh context returned nothing");
+        // SAFETY: `entry` is valid by the contract of `new_on`.
+        unsafe { (self.entry)(cx, t.data) }
+    }
+}
+
+/// The [`Resume`] implementation that runs every context on a separate thread.
+///
+/// See [the module-level documentation](self) for more information.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Thread;
+
+#[derive(Debug)]
+pub enum NewError {
+    StackTooSmall,
+    Spawn(IoError),
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::StackTooSmall => {
+                f.write_str("the stack is too small for a context")
+            }
+            NewError::Spawn(err) => {
+                write!(f, "failed to spawn the thread of a context: {err}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for NewError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            NewError::StackTooSmall => None,
+            NewError::Spawn(err) => Some(err),
+        }
+    }
+}
+
+impl From<NewError> for crate::NewError {
+    fn from(err: NewError) -> Self {
+        crate::NewError::Thread(err)
+    }
+}
+
+// SAFETY: Only one context is running at a time, and every switch hands off
+// the execution with a lock, which synchronizes the memory accesses.
+unsafe impl Resume for Thread {
+    type Context = Tcx;
+
+    type NewError = NewError;
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new::<Tcx>()
+            .preserves_fp_state(true)
+            .preserves_signal_mask(true)
+            .migratable(true)
+            .on_stack(false)
+    }
+
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<Tcx>,
+    ) -> Result<NonNull<Tcx>, NewError> {
+        // The stack is not used, but is still checked for consistency with the
+        // other backends.
+        if stack.len() < min_stack::<Tcx>().size() {
+            return Err(NewError::StackTooSmall);
+        }
+        let tcx = Tcx::new();
+        let start = Start { tcx, entry };
+        thread::Builder::new()
+            .name("unico-context".into())
+            .spawn(move || start.run())
+            .map_err(NewError::Spawn)?;
+        Ok(tcx)
+    }
+
+    unsafe fn resume(&self, cx: NonNull<Tcx>, data: *mut ()) -> Transfer {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, data, None) }
+    }
+
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<Tcx>,
+        data: *mut (),
+        map: Map<Tcx>,
+    ) -> Transfer {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, data, Some(map)) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ptr::{self, NonNull};
+    use std::{vec, vec::Vec};
+
+    use super::{Tcx, Thread, Transfer};
+    use crate::Resume;
+
+    fn stack() -> Vec<u8> {
+        vec![0; 4096]
+    }
+
+    unsafe extern "C" fn count(mut cx: NonNull<Tcx>, data: *mut ()) -> ! {
+        let mut n = data.addr();
+        loop {
+            n += 1;
+            let t = unsafe { Thread.resume(cx, ptr::without_provenance_mut(n)) };
+            cx = t.context.unwrap();
+            n = t.data.addr();
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let mut cx = unsafe { Thread.new_on(stack, count) }.unwrap();
+        for i in 0..100 {
+            let t = unsafe { Thread.resume(cx, ptr::without_provenance_mut(i)) };
+            assert_eq!(t.data.addr(), i + 1);
+            cx = t.context.unwrap();
+        }
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C-unwind" fn double(cx: NonNull<Tcx>, data: *mut ()) -> Transfer {
+        Transfer {
+            context: Some(cx),
+            data: ptr::without_provenance_mut(data.addr() * 2),
+        }
+    }
+
+    #[test]
+    fn map() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { Thread.new_on(stack, count) }.unwrap();
+        // `map` runs on the thread of the fresh context before `count` starts.
+        let t = unsafe { Thread.resume_with(cx, ptr::without_provenance_mut(3), double) };
+        assert_eq!(t.data.addr(), 7);
+        // ... and on the thread of a suspended context as well.
+        let cx = t.context.unwrap();
+        let t = unsafe { Thread.resume_with(cx, ptr::without_provenance_mut(5), double) };
+        assert_eq!(t.data.addr(), 11);
+    }
+}
@@ -0,0 +1,29 @@
+/*
+ * Context switching without touching the signal mask, used by `UcontextNoMask`
+ * in `ucx.rs`.
+ *
+ * `_longjmp` to another stack is checked by `__longjmp_chk` if fortified, which
+ * is not what we want.
+ */
+#undef _FORTIFY_SOURCE
+#include <setjmp.h>
+#include <ucontext.h>
+
+/* Keep in sync with `JmpBuf` in `ucx.rs`. */
+_Static_assert(sizeof(jmp_buf) <= 1024, "`jmp_buf` is too large");
+
+/* Saves the current context in `from`, and jumps to `to`. Returns when some
+ * other context jumps back to `from`. */
+void unico_ucx_jump(jmp_buf *from, jmp_buf *to) {
+    if (!_setjmp(*from)) {
+        _longjmp(*to, 1);
+    }
+}
+
+/* Saves the current context in `from`, and starts `to` for the first time.
+ * Returns when some other context jumps back to `from`. */
+void unico_ucx_start(jmp_buf *from, const ucontext_t *to) {
+    if (!_setjmp(*from)) {
+        setcontext(to);
+    }
+}
@@ -1,66 +1,198 @@
 use core::{
-    cell::Cell,
-    mem,
+    cell::{Cell, UnsafeCell},
+    fmt, mem,
     ptr::{self, NonNull},
 };
-use std::{boxed::Box, io::Error as IoError};
+use std::{io::Error as IoError, thread::LocalKey};
 
-use libc::ucontext_t;
+use libc::{sigset_t, ucontext_t};
 
-use crate::{Entry, Map, Resume, stack_top};
+use crate::{Capabilities, Entry, Map, Resume, stack_top};
 
-type Transfer = crate::Transfer<ucontext_t>;
+type Transfer<C = ucontext_t> = crate::Transfer<C>;
 
+// The records of the root contexts live in the thread-local storage, so that
+// they are reclaimed on thread exit. They have no destructors, and thus remain
+// valid until all the other thread-local destructors (which may still switch
+// contexts) are run.
 std::thread_local! {
-    static TRANSFER: Cell<LocalTransfer> = {
-        Cell::new(LocalTransfer {
+    // SAFETY: All the fields are valid if zeroed, and the record is always
+    // saved by `swapcontext` before being switched to.
+    static ROOT: UnsafeCell<ucontext_t> =
+        const { UnsafeCell::new(unsafe { mem::zeroed() }) };
+
+    static ROOT_NO_MASK: UnsafeCell<Jcx> = const { UnsafeCell::new(Jcx::root()) };
+
+    static TRANSFER: Cell<LocalTransfer<ucontext_t>> =
+        Cell::new(LocalTransfer::new(root(&ROOT)));
+
+    static TRANSFER_NO_MASK: Cell<LocalTransfer<Jcx>> =
+        Cell::new(LocalTransfer::new(root(&ROOT_NO_MASK)));
+}
+
+fn root<C>(key: &'static LocalKey<UnsafeCell<C>>) -> NonNull<C> {
+    key.with(|root| NonNull::new(root.get()).unwrap())
+}
+
+#[derive(Debug)]
+struct LocalTransfer<C> {
+    from: Option<NonNull<C>>,
+    ucx: NonNull<C>,
+    on_top: Option<Map<C>>,
+    data: *mut (),
+}
+
+impl<C> LocalTransfer<C> {
+    fn new(root: NonNull<C>) -> Self {
+        LocalTransfer {
             from: None,
-            ucx: new_root(),
+            ucx: root,
             on_top: None,
             data: ptr::null_mut(),
-        })
-    };
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct LocalTransfer {
-    from: Option<NonNull<ucontext_t>>,
-    ucx: NonNull<ucontext_t>,
-    on_top: Option<Map<ucontext_t>>,
-    data: *mut (),
+impl<C> Clone for LocalTransfer<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for LocalTransfer<C> {}
+
+/// The context records that can be switched by the functions in this module.
+trait Record: Sized + 'static {
+    fn local() -> &'static LocalKey<Cell<LocalTransfer<Self>>>;
+
+    /// The `ucontext_t` used for the first entry of the context.
+    fn ucontext(this: NonNull<Self>) -> *mut ucontext_t;
+
+    /// Initializes the other fields of a fresh record, whose `ucontext_t` is
+    /// already initialized.
+    ///
+    /// # Safety
+    ///
+    /// `this` must be valid for writes.
+    unsafe fn init(_this: NonNull<Self>) {}
+
+    /// Saves the current context in `src`, and switches to `target`.
+    ///
+    /// # Safety
+    ///
+    /// Both records must be valid.
+    unsafe fn switch(src: NonNull<Self>, target: NonNull<Self>);
+}
+
+impl Record for ucontext_t {
+    fn local() -> &'static LocalKey<Cell<LocalTransfer<Self>>> {
+        &TRANSFER
+    }
+
+    fn ucontext(this: NonNull<Self>) -> *mut ucontext_t {
+        this.as_ptr()
+    }
+
+    unsafe fn switch(src: NonNull<Self>, target: NonNull<Self>) {
+        // SAFETY: Both pointers have their reference to a valid `ucontext_t`
+        // respectively.
+        let status = unsafe { libc::swapcontext(src.as_ptr(), target.as_ptr()) };
+        assert_eq!(
+            status,
+            0,
+            "failed to swap context: {:?}",
+            IoError::last_os_error()
+        );
+    }
+}
+
+/// The storage of `jmp_buf`, whose size is checked in `ucx.c`.
+#[repr(C, align(16))]
+struct JmpBuf([u8; 1024]);
+
+/// The context record of [`UcontextNoMask`].
+///
+/// The `ucontext_t` is only used for the first entry of the context, while the
+/// following switches are performed with `_setjmp` and `_longjmp`.
+#[repr(C)]
+pub struct Jcx {
+    ucx: ucontext_t,
+    jmp: JmpBuf,
+    started: bool,
+}
+
+// SAFETY: These functions are implemented in `ucx.c`.
+#[link(name = "unico_ucx")]
+unsafe extern "C" {
+    fn unico_ucx_jump(from: *mut JmpBuf, to: *mut JmpBuf);
+
+    fn unico_ucx_start(from: *mut JmpBuf, to: *const ucontext_t);
 }
 
-/// The wrapper type of a pointer to `ucontext_t` with some additional data.
+impl Jcx {
+    const fn root() -> Jcx {
+        Jcx {
+            // SAFETY: All the fields are valid if zeroed. The `ucontext_t` of
+            // the root context is never used.
+            ucx: unsafe { mem::zeroed() },
+            jmp: JmpBuf([0; 1024]),
+            // The root context is always running when created.
+            started: true,
+        }
+    }
+}
 
-fn new_root() -> NonNull<ucontext_t> {
-    let mut ret = Box::new_uninit();
-    // SAFETY: `ret` contains a valid block of memory, waiting to be initialized.
-    let status = unsafe { libc::getcontext(ret.as_mut_ptr()) };
-    assert_eq!(
-        status,
-        0,
-        "Failed to construct top level context: {:?}",
-        IoError::last_os_error()
-    );
-    // SAFETY: `ret` is initialized by `libc::getcontext`.
-    NonNull::from(Box::leak(unsafe { ret.assume_init() }))
+impl Record for Jcx {
+    fn local() -> &'static LocalKey<Cell<LocalTransfer<Self>>> {
+        &TRANSFER_NO_MASK
+    }
+
+    fn ucontext(this: NonNull<Self>) -> *mut ucontext_t {
+        // SAFETY: The field is in bounds of `this`.
+        unsafe { &raw mut (*this.as_ptr()).ucx }
+    }
+
+    unsafe fn init(this: NonNull<Self>) {
+        // SAFETY: `this` is valid for writes by contract.
+        unsafe { (&raw mut (*this.as_ptr()).started).write(false) }
+    }
+
+    unsafe fn switch(src: NonNull<Self>, target: NonNull<Self>) {
+        let (src, target) = (src.as_ptr(), target.as_ptr());
+        // SAFETY: Both records are valid by contract. The signal mask is only set
+        // by `setcontext` for the first entry of `target`.
+        unsafe {
+            if (*target).started {
+                unico_ucx_jump(&raw mut (*src).jmp, &raw mut (*target).jmp)
+            } else {
+                (*target).started = true;
+                unico_ucx_start(&raw mut (*src).jmp, &raw const (*target).ucx)
+            }
+        }
+    }
 }
 
 /// # Safety
 ///
 /// See [`Resume::new_on`] for more information.
-unsafe fn new_on(
+unsafe fn new_on<C: Record>(
     stack: NonNull<[u8]>,
-    entry: Entry<ucontext_t>,
-) -> Result<NonNull<ucontext_t>, NewError> {
+    entry: Entry<C>,
+) -> Result<NonNull<C>, NewError> {
+    // Nothing is above this function on the stack, so it doesn't unwind.
     #[allow(improper_ctypes_definitions)]
-    unsafe extern "C" fn wrapper(entry: Entry<ucontext_t>) {
-        let t = TRANSFER.get();
-        entry(t.from.unwrap(), t.data);
+    unsafe extern "C" fn wrapper<C: Record>(entry: Entry<C>) {
+        // SAFETY: The context is switched to by `resume_with`.
+        let t = unsafe { received::<C>() };
+        let cx = t
+            .context
+            .expect("the map of a fresh context returned nothing");
+        // SAFETY: `entry` is valid by the contract of `new_on`.
+        unsafe { entry(cx, t.data) }
     }
 
-    let pointer: NonNull<ucontext_t> = stack_top(stack).ok_or(NewError::StackTooSmall)?;
-    let ucx = pointer.as_ptr();
+    let pointer: NonNull<C> = stack_top(stack).ok_or(NewError::StackTooSmall)?;
+    let ucx = C::ucontext(pointer);
 
     // SAFETY: `ucx` is proper aligned and points to a valid block of uninitialized
     // memory.
@@ -76,12 +208,15 @@ unsafe fn new_on(
         ucx.uc_stack.ss_sp = stack.as_ptr().cast();
         ucx.uc_stack.ss_size = pointer.as_ptr().byte_offset_from(ucx.uc_stack.ss_sp) as _;
         ucx.uc_link = ptr::null_mut();
+        C::init(pointer);
     }
 
     // SAFETY: `ucx` is initialized by `libc::getcontext`; `wrapper` has exactly 1
     // parameter.
     unsafe {
-        let wrapper = mem::transmute(wrapper as unsafe extern "C" fn(_));
+        let wrapper = mem::transmute::<unsafe extern "C" fn(Entry<C>), extern "C" fn()>(
+            wrapper::<C>,
+        );
         libc::makecontext(ucx, wrapper, 1, entry)
     };
 
@@ -91,33 +226,44 @@ unsafe fn new_on(
 /// # Safety
 ///
 /// See [`Resume::resume`] for more information.
-unsafe fn resume_with(
-    target: NonNull<ucontext_t>,
-    on_top: Option<Map<ucontext_t>>,
+unsafe fn resume_with<C: Record>(
+    target: NonNull<C>,
+    on_top: Option<Map<C>>,
     data: *mut (),
-) -> Transfer {
-    let src = TRANSFER.get().ucx;
-    TRANSFER.set(LocalTransfer {
+) -> Transfer<C> {
+    let local = C::local();
+    let src = local.get().ucx;
+    local.set(LocalTransfer {
         from: Some(src),
         ucx: target,
         on_top,
         data,
     });
 
-    // SAFETY: Both pointers have their reference to a valid `ucontext_t`
-    // respectively.
-    let status = unsafe { libc::swapcontext(src.as_ptr(), target.as_ptr()) };
-    assert_eq!(
-        status,
-        0,
-        "failed to swap context: {:?}",
-        IoError::last_os_error()
-    );
-
-    let t = TRANSFER.get();
+    // SAFETY: Both records are valid by contract.
+    unsafe { C::switch(src, target) };
+    // SAFETY: The context is switched back by `resume_with`.
+    unsafe { received() }
+}
+
+/// Receives the transfer structure after switched to, executing the map
+/// function on top of the current stack if any.
+///
+/// The map function may unwind, since it is called after the switch has
+/// completed, with no frames of `swapcontext` or `_longjmp` in between. The
+/// exception is the first switch to a fresh context, whose map function is
+/// called by `wrapper` at the bottom of the stack. There is no frame to unwind
+/// to, so the process aborts if that map function unwinds.
+///
+/// # Safety
+///
+/// The current context must be switched to by [`resume_with`].
+unsafe fn received<C: Record>() -> Transfer<C> {
+    let t = C::local().get();
     let ucx = t.from.unwrap();
     match t.on_top {
-        Some(on_top) => on_top(ucx, t.data),
+        // SAFETY: `on_top` is valid by the contract of `resume_with`.
+        Some(on_top) => unsafe { on_top(ucx, t.data) },
         None => Transfer {
             context: Some(ucx),
             data: t.data,
@@ -126,15 +272,60 @@ unsafe fn resume_with(
 }
 
 /// The [`Resume`] implementation with the POSIX library's [`makecontext`](https://man7.org/linux/man-pages/man3/makecontext.3.html) functionalities.
+///
+/// Every switch saves and restores the signal mask of the context, which costs
+/// a system call. See [`UcontextNoMask`] for a cheaper alternative.
 #[derive(Debug, Copy, Clone, Default)]
 pub struct Ucontext;
 
+/// Like [`Ucontext`], but doesn't save or restore the signal mask.
+///
+/// The context is entered for the first time with `setcontext`, and then
+/// switched with `_setjmp` and `_longjmp`, so that no system call is made
+/// afterwards.
+///
+/// As a consequence, the signal mask is shared among all the contexts on the
+/// same thread, except that a new context starts with the signal mask at the
+/// time of its creation. That is, blocking a signal in one context keeps it
+/// blocked after switching to another. Use [`SigMask`] to restore per-context
+/// signal masks if really needed.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct UcontextNoMask;
+
 #[derive(Debug)]
 pub enum NewError {
     StackTooSmall,
     GetContext(IoError),
 }
 
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::StackTooSmall => {
+                f.write_str("the stack is too small for a context")
+            }
+            NewError::GetContext(err) => {
+                write!(f, "failed to get the current context: {err}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for NewError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            NewError::StackTooSmall => None,
+            NewError::GetContext(err) => Some(err),
+        }
+    }
+}
+
+impl From<NewError> for crate::NewError {
+    fn from(err: NewError) -> Self {
+        crate::NewError::Ucx(err)
+    }
+}
+
 // SAFETY: The `ucontext_t` is created on the given stack. See `self::new_on`
 // for more information.
 unsafe impl Resume for Ucontext {
@@ -142,16 +333,26 @@ unsafe impl Resume for Ucontext {
 
     type NewError = NewError;
 
+    fn capabilities(&self) -> Capabilities {
+        // The records are switched through thread-local storage, which may be
+        // cached across switches.
+        Capabilities::new::<ucontext_t>()
+            .preserves_fp_state(true)
+            .preserves_signal_mask(true)
+    }
+
     unsafe fn new_on(
         &self,
         stack: NonNull<[u8]>,
         entry: Entry<ucontext_t>,
     ) -> Result<NonNull<ucontext_t>, NewError> {
-        new_on(stack, entry)
+        // SAFETY: The contract is the same.
+        unsafe { new_on(stack, entry) }
     }
 
     unsafe fn resume(&self, cx: NonNull<ucontext_t>, data: *mut ()) -> Transfer {
-        resume_with(cx, None, data)
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, None, data) }
     }
 
     unsafe fn resume_with(
@@ -159,7 +360,280 @@ unsafe impl Resume for Ucontext {
         cx: NonNull<ucontext_t>,
         data: *mut (),
         map: Map<ucontext_t>,
-    ) -> crate::Transfer<ucontext_t> {
-        resume_with(cx, Some(map), data)
+    ) -> Transfer {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, Some(map), data) }
+    }
+}
+
+// SAFETY: The `Jcx` is created on the given stack. See `self::new_on` for more
+// information.
+unsafe impl Resume for UcontextNoMask {
+    type Context = Jcx;
+
+    type NewError = NewError;
+
+    fn capabilities(&self) -> Capabilities {
+        // The records are switched through thread-local storage, which may be
+        // cached across switches.
+        Capabilities::new::<Jcx>()
+    }
+
+    unsafe fn new_on(
+        &self,
+        stack: NonNull<[u8]>,
+        entry: Entry<Jcx>,
+    ) -> Result<NonNull<Jcx>, NewError> {
+        // SAFETY: The contract is the same.
+        unsafe { new_on(stack, entry) }
+    }
+
+    unsafe fn resume(&self, cx: NonNull<Jcx>, data: *mut ()) -> Transfer<Jcx> {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, None, data) }
+    }
+
+    unsafe fn resume_with(
+        &self,
+        cx: NonNull<Jcx>,
+        data: *mut (),
+        map: Map<Jcx>,
+    ) -> Transfer<Jcx> {
+        // SAFETY: The contract is the same.
+        unsafe { resume_with(cx, Some(map), data) }
+    }
+}
+
+/// A signal mask that can be switched on as the current thread's one.
+///
+/// Since [`UcontextNoMask`] shares the signal mask among contexts, users who
+/// need per-context signal masks can store this structure alongside with each
+/// context, and switch it on right after the context is resumed, e.g. as the
+/// metadata of tasks in `unico_async::sym`.
+#[derive(Clone, Copy)]
+pub struct SigMask(sigset_t);
+
+impl SigMask {
+    /// Wraps a signal set as a signal mask.
+    pub const fn new(set: sigset_t) -> Self {
+        SigMask(set)
+    }
+
+    /// The signal mask of the current thread.
+    pub fn current() -> Self {
+        let mut set = mem::MaybeUninit::uninit();
+        // SAFETY: `set` is valid for writes.
+        let status = unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, ptr::null(), set.as_mut_ptr())
+        };
+        assert_eq!(status, 0, "failed to get the signal mask");
+        // SAFETY: `set` is initialized by `libc::pthread_sigmask`.
+        SigMask(unsafe { set.assume_init() })
+    }
+
+    /// The underlying signal set.
+    pub fn as_sigset(&self) -> &sigset_t {
+        &self.0
+    }
+
+    /// Switches on `self` as the signal mask of the current thread, and returns
+    /// the old one.
+    pub fn switch(self) -> Self {
+        let mut old = mem::MaybeUninit::uninit();
+        // SAFETY: Both pointers are valid.
+        let status = unsafe {
+            libc::pthread_sigmask(libc::SIG_SETMASK, &self.0, old.as_mut_ptr())
+        };
+        assert_eq!(status, 0, "failed to switch the signal mask");
+        // SAFETY: `old` is initialized by `libc::pthread_sigmask`.
+        SigMask(unsafe { old.assume_init() })
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use core::{
+        cell::Cell,
+        mem::MaybeUninit,
+        panic::AssertUnwindSafe,
+        ptr::{self, NonNull},
+    };
+    use std::{panic, thread, vec, vec::Vec};
+
+    use super::{Jcx, SigMask, Ucontext, UcontextNoMask};
+    use crate::{Resume, Transfer};
+
+    fn stack() -> Vec<u8> {
+        vec![0; 4096 * 16]
+    }
+
+    fn is_blocked(signal: i32) -> bool {
+        unsafe { libc::sigismember(SigMask::current().as_sigset(), signal) == 1 }
+    }
+
+    /// Blocks `SIGUSR1` and yields back forever.
+    unsafe extern "C" fn block<R: Resume + Default>(
+        mut cx: NonNull<R::Context>,
+        _: *mut (),
+    ) -> ! {
+        unsafe {
+            let mut set = MaybeUninit::uninit();
+            libc::sigemptyset(set.as_mut_ptr());
+            libc::sigaddset(set.as_mut_ptr(), libc::SIGUSR1);
+            libc::pthread_sigmask(libc::SIG_BLOCK, set.as_ptr(), ptr::null_mut());
+        }
+        loop {
+            cx = unsafe { R::default().resume(cx, ptr::null_mut()) }
+                .context
+                .unwrap();
+        }
+    }
+
+    unsafe extern "C" fn count(mut cx: NonNull<Jcx>, data: *mut ()) -> ! {
+        let mut n = data.addr();
+        loop {
+            n += 1;
+            let t = unsafe { UcontextNoMask.resume(cx, ptr::without_provenance_mut(n)) };
+            cx = t.context.unwrap();
+            n = t.data.addr();
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let mut cx = unsafe { UcontextNoMask.new_on(stack, count) }.unwrap();
+        for i in 0..100 {
+            let t = unsafe { UcontextNoMask.resume(cx, ptr::without_provenance_mut(i)) };
+            assert_eq!(t.data.addr(), i + 1);
+            cx = t.context.unwrap();
+        }
+    }
+
+    /// Counts like `count`, but generic over the backends.
+    unsafe extern "C" fn count_any<R: Resume + Default>(
+        mut cx: NonNull<R::Context>,
+        data: *mut (),
+    ) -> ! {
+        let mut n = data.addr();
+        loop {
+            n += 1;
+            let t = unsafe { R::default().resume(cx, ptr::without_provenance_mut(n)) };
+            cx = t.context.unwrap();
+            n = t.data.addr();
+        }
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C-unwind" fn double<C>(cx: NonNull<C>, data: *mut ()) -> Transfer<C> {
+        Transfer {
+            context: Some(cx),
+            data: ptr::without_provenance_mut(data.addr() * 2),
+        }
+    }
+
+    fn check_map<R: Resume + Default>() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let rs = R::default();
+        let cx = unsafe { rs.new_on(stack, count_any::<R>) }.unwrap();
+        // `map` runs on top of the fresh stack before `count_any` starts.
+        let t = unsafe { rs.resume_with(cx, ptr::without_provenance_mut(3), double) };
+        assert_eq!(t.data.addr(), 7);
+        // ... and on top of a suspended stack as well.
+        let cx = t.context.unwrap();
+        let t = unsafe { rs.resume_with(cx, ptr::without_provenance_mut(5), double) };
+        assert_eq!(t.data.addr(), 11);
+    }
+
+    #[test]
+    fn map() {
+        check_map::<Ucontext>();
+        check_map::<UcontextNoMask>();
+    }
+
+    std::thread_local! {
+        /// The context passed to `panicking`.
+        static PANICKED: Cell<*mut ()> = const { Cell::new(ptr::null_mut()) };
+    }
+
+    #[allow(improper_ctypes_definitions)]
+    unsafe extern "C-unwind" fn panicking<C>(cx: NonNull<C>, _: *mut ()) -> Transfer<C> {
+        PANICKED.set(cx.as_ptr().cast());
+        panic!("unwinding from a map")
+    }
+
+    /// Catches the panic from `panicking`, and reports it to the resumer.
+    unsafe extern "C" fn catch<R: Resume + Default>(
+        cx: NonNull<R::Context>,
+        _: *mut (),
+    ) -> ! {
+        let rs = R::default();
+        let mut cx = Some(cx);
+        let ret = panic::catch_unwind(AssertUnwindSafe(|| {
+            loop {
+                let t = unsafe { rs.resume(cx.take().unwrap(), ptr::null_mut()) };
+                cx = t.context;
+            }
+        }));
+        assert!(ret.is_err());
+        let cx = NonNull::new(PANICKED.get()).unwrap().cast();
+        unsafe { rs.resume(cx, ptr::without_provenance_mut(1)) };
+        unreachable!()
+    }
+
+    fn check_map_unwind<R: Resume + Default>() {
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let rs = R::default();
+        let cx = unsafe { rs.new_on(stack, catch::<R>) }.unwrap();
+        let t = unsafe { rs.resume(cx, ptr::null_mut()) };
+        let t = unsafe { rs.resume_with(t.context.unwrap(), ptr::null_mut(), panicking) };
+        assert_eq!(t.data.addr(), 1);
+    }
+
+    #[test]
+    fn map_unwind() {
+        check_map_unwind::<Ucontext>();
+        check_map_unwind::<UcontextNoMask>();
+    }
+
+    #[test]
+    fn root_per_thread() {
+        let threads = (0..4).map(|_| thread::spawn(check_map::<Ucontext>));
+        threads.for_each(|t| t.join().unwrap());
+    }
+
+    #[test]
+    fn mask_isolated() {
+        assert!(!is_blocked(libc::SIGUSR1));
+
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx = unsafe { Ucontext.new_on(stack, block::<Ucontext>) }.unwrap();
+        unsafe { Ucontext.resume(cx, ptr::null_mut()) };
+        assert!(!is_blocked(libc::SIGUSR1));
+    }
+
+    #[test]
+    fn mask_shared() {
+        let mask = SigMask::current();
+        assert!(!is_blocked(libc::SIGUSR1));
+
+        let mut stack = stack();
+        let stack = NonNull::from(&mut stack[..]);
+        let cx =
+            unsafe { UcontextNoMask.new_on(stack, block::<UcontextNoMask>) }.unwrap();
+        unsafe { UcontextNoMask.resume(cx, ptr::null_mut()) };
+        assert!(is_blocked(libc::SIGUSR1));
+
+        // Restore the signal mask explicitly.
+        let blocked = mask.switch();
+        assert!(!is_blocked(libc::SIGUSR1));
+        assert_eq!(
+            unsafe { libc::sigismember(blocked.as_sigset(), libc::SIGUSR1) },
+            1
+        );
     }
 }
@@ -5,10 +5,10 @@ use std::{alloc::Global, io::Read};
 use futures_lite::{AsyncRead, AsyncReadExt};
 use spin_on::spin_on;
 use unico::asym::{AsymWait, sync};
-use unico_context::{boost::Boost, global_resumer};
+use unico_context::{DefaultResumer, global_resumer};
 use unico_stack::global_stack_allocator;
 
-global_resumer!(Boost);
+global_resumer!(DefaultResumer);
 global_stack_allocator!(Global);
 
 struct Synced<R>(R);
@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(unico_fiber)");
+
+    // The features tracking the running stack across switches in `sym::fiber`.
+    #[cfg(any(
+        feature = "asan",
+        feature = "tsan",
+        feature = "valgrind",
+        feature = "overflow",
+        feature = "paint",
+        feature = "grow"
+    ))]
+    println!("cargo::rustc-cfg=unico_fiber");
+}
@@ -11,7 +11,7 @@ use core::{
     ptr,
 };
 
-use unico_stack::{Global, Stack};
+use unico_stack::{Global, IntoStack};
 
 #[cfg(any(feature = "unwind", feature = "std"))]
 use crate::unwind::*;
@@ -92,7 +92,7 @@ impl Gn<'static, ()> {
 impl<'a, F, C, Y, R, S, P> Build<F, S, P> for Gn<'a, C, Y, R>
 where
     F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + Send + 'a,
-    S: Into<Stack>,
+    S: IntoStack<'a>,
     P: PanicHook,
 {
     fn build(builder: Builder<S, P>, arg: F) -> Result<Self, Self::Error> {
@@ -101,10 +101,10 @@ where
     }
 }
 
-impl<F, C, Y, R, S, P> BuildUnchecked<F, S, P> for Gn<'_, C, Y, R>
+impl<'a, F, C, Y, R, S, P> BuildUnchecked<F, S, P> for Gn<'a, C, Y, R>
 where
     F: FnOnce(&mut YieldHandle<Y, R>, R) -> C,
-    S: Into<Stack>,
+    S: IntoStack<'a>,
     P: PanicHook,
 {
     type Error = NewError;
@@ -114,6 +114,9 @@ where
     /// - `func` must be [`Send`], or the caller must not send the coroutine to
     ///   another thread.
     /// - `func` must be at least `'a`.
+    ///
+    /// The stack only needs to be valid for `'a` as well, since the generator
+    /// cannot outlive it.
     unsafe fn build_unchecked(
         builder: Builder<S, P>,
         func: F,
@@ -157,13 +160,35 @@ where
         // generator itself, and the yield handle cannot escape the function as well.
         // Besides, `func` is `Send`. Also see step 0 of the type's safety notice.
         Ok(Gn {
-            inner: unsafe { builder.callcc_unchecked(wrapper) }?,
+            inner: unsafe { builder.unbounded().callcc_unchecked(wrapper) }?,
             marker: PhantomData,
         })
     }
 }
 
 impl<C, Y, R> Gn<'_, C, Y, R> {
+    /// The largest size of the stack of this coroutine ever used, in bytes.
+    ///
+    /// Returns `None` if the coroutine is complete. See [`Co::high_water_mark`]
+    /// for more information.
+    #[cfg(feature = "paint")]
+    pub fn high_water_mark(&self) -> Option<usize> {
+        self.inner.as_ref()?.high_water_mark()
+    }
+
+    /// Whether the coroutine is complete, or has panicked.
+    #[cfg(feature = "shared")]
+    pub(crate) fn is_complete(&self) -> bool {
+        self.inner.is_none()
+    }
+
+    /// The address of the suspended context, or `None` if the coroutine is
+    /// complete.
+    #[cfg(feature = "shared")]
+    pub(crate) fn context(&self) -> Option<usize> {
+        self.inner.as_ref().map(Co::context)
+    }
+
     pub fn resume(&mut self, resumed: R) -> CoroutineState<Y, C> {
         let co = (self.inner.take()).expect("coroutine resumed after completion");
         let mut m = MaybeUninit::new(resumed);
@@ -215,9 +240,11 @@ impl<Y, R> YieldHandle<Y, R> {
 
 #[cfg(test)]
 mod tests {
-    use core::ops::CoroutineState;
+    use core::{mem::MaybeUninit, ops::CoroutineState};
+
+    use unico_stack::BorrowedStack;
 
-    use crate::r#gen;
+    use crate::{r#gen, gen_on};
 
     #[test]
     fn basic() {
@@ -242,6 +269,23 @@ mod tests {
         r#gen::<_, _, (), _>(|_, _| panic!("What the fuck?")).resume(());
     }
 
+    #[test]
+    fn borrowed_stack() {
+        let mut memory = [MaybeUninit::uninit(); 32768];
+        let mut sum = 0;
+        let mut gn = gen_on(BorrowedStack::new(&mut memory), |y, mut r| {
+            for i in 0..10 {
+                sum += r;
+                r = y.yield_(i);
+            }
+            sum + r
+        });
+        for i in 0..10 {
+            assert!(matches!(gn.resume(i), CoroutineState::Yielded(x) if x == i));
+        }
+        assert!(matches!(gn.resume(10), CoroutineState::Complete(55)));
+    }
+
     #[test]
     fn destruct() {
         let mut g = r#gen(|y, ()| y.yield_(()));
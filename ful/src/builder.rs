@@ -1,4 +1,7 @@
-use unico_stack::{Global, Stack};
+use core::{alloc::Layout, marker::PhantomData};
+
+use unico_context::{self as cx, Capabilities, DynResume};
+use unico_stack::{Global, IntoStack, Stack};
 
 use crate::{
     NewError,
@@ -7,10 +10,15 @@ use crate::{
 };
 
 /// The generic builder for the initialization of some coroutine.
+///
+/// The resumer is only set with the unsafe [`Builder::resumed_by`], so the
+/// builder can't be constructed with a struct literal. Start from
+/// [`Builder::new`] or [`Builder::default`] and update the fields instead.
 #[derive(Debug, Clone, Copy)]
 pub struct Builder<S, P> {
     pub stack: S,
     pub panic_hook: P,
+    resumer: &'static dyn DynResume,
 }
 
 impl Default for Builder<(), AbortHook> {
@@ -18,10 +26,27 @@ impl Default for Builder<(), AbortHook> {
         Builder {
             stack: (),
             panic_hook: AbortHook,
+            resumer: &cx::Global,
         }
     }
 }
 
+/// A source of stacks valid for `'a`, regarded as valid forever.
+pub(crate) struct Unbounded<'a, S>(S, PhantomData<&'a ()>);
+
+// SAFETY: The coroutines on the stack don't outlive `'a`, by the contract of
+// `Builder::unbounded`.
+unsafe impl<'a, S: IntoStack<'a>> IntoStack<'static> for Unbounded<'a, S> {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        // SAFETY: Same as above.
+        unsafe { self.0.into_stack() }
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        self.0.layout()
+    }
+}
+
 /// Build a stackful-coroutine-type object from the builder.
 pub trait Build<F, S, P>: BuildUnchecked<F, S, P> {
     /// Build a stackful-coroutine-type object from the builder.
@@ -49,6 +74,7 @@ impl Builder<&'static Global, AbortHook> {
         Builder {
             stack: &Global,
             panic_hook: AbortHook,
+            resumer: &cx::Global,
         }
     }
 }
@@ -59,6 +85,7 @@ impl<S, P> Builder<S, P> {
         Builder {
             stack,
             panic_hook: self.panic_hook,
+            resumer: self.resumer,
         }
     }
 
@@ -70,9 +97,56 @@ impl<S, P> Builder<S, P> {
         Builder {
             stack: self.stack,
             panic_hook: hook,
+            resumer: self.resumer,
         }
     }
 
+    /// Set the resumer that creates and switches the contexts of the
+    /// coroutine. Defaults to the global resumer defined by
+    /// [`global_resumer`](unico_context::global_resumer).
+    ///
+    /// # Safety
+    ///
+    /// The coroutine may only transfer the control flow to other coroutines
+    /// (including the root control flow) switched by the same resumer
+    /// implementation, since each implementation has its own layout of saved
+    /// contexts. For example, a coroutine resumed by `Ucontext` must not
+    /// switch to the one resumed by the global resumer, unless the latter is
+    /// also `Ucontext`.
+    pub unsafe fn resumed_by(self, resumer: &'static dyn DynResume) -> Self {
+        Builder { resumer, ..self }
+    }
+
+    /// Regards the stack, which may only be valid for `'a`, as valid forever.
+    ///
+    /// # Safety
+    ///
+    /// The coroutine built from the returned builder must not outlive `'a`.
+    pub(crate) unsafe fn unbounded<'a>(self) -> Builder<Unbounded<'a, S>, P>
+    where
+        S: IntoStack<'a>,
+    {
+        Builder {
+            stack: Unbounded(self.stack, PhantomData),
+            panic_hook: self.panic_hook,
+            resumer: self.resumer,
+        }
+    }
+
+    /// The resumer that creates and switches the contexts of the coroutine.
+    pub fn resumer(&self) -> &'static dyn DynResume {
+        self.resumer
+    }
+
+    /// The static properties of the resumer.
+    ///
+    /// Stacks smaller than [`Capabilities::min_stack`] plus the header of the
+    /// coroutine are rejected with [`NewError::StackTooSmall`], before they are
+    /// allocated if their layouts are [known](IntoStack::layout).
+    pub fn capabilities(&self) -> Capabilities {
+        self.resumer.capabilities()
+    }
+
     /// Build a stackful-coroutine-type object from the builder.
     pub fn build<T, F>(self, arg: F) -> Result<T, T::Error>
     where
@@ -97,7 +171,7 @@ impl<S, P> Builder<S, P> {
     }
 }
 
-impl<S: Into<Stack>, P: PanicHook> Builder<S, P> {
+impl<S: IntoStack<'static>, P: PanicHook> Builder<S, P> {
     /// Create a symmetric stackful coroutine.
     ///
     /// Unlike [`Builder::callcc`], the function will not be executed upon
@@ -152,14 +226,62 @@ impl<S: Into<Stack>, P: PanicHook> Builder<S, P> {
         F: FnOnce(Co) -> Co,
     {
         // SAFETY: The contract is the same.
-        unsafe { Co::callcc_unchecked(func, self.stack, self.panic_hook) }
+        unsafe { Co::callcc_unchecked(func, self) }.map(|(co, _)| co)
     }
+}
 
+impl<S: IntoStack<'static>, P> Builder<S, P> {
+    /// Create a symmetric stackful coroutine passing values of `T` alongside
+    /// every transfer of the control flow.
+    ///
+    /// Besides the continuation that first resumes the coroutine, the function
+    /// receives the value sent alongside, if any. See [`Co::resume_with_value`]
+    /// for more information.
+    pub fn spawn_typed<T, F>(self, func: F) -> Result<Co<T>, NewError>
+    where
+        T: Send + 'static,
+        P: PanicHook<T>,
+        F: FnOnce(Option<Co<T>>, Option<T>) -> Co<T> + Send + 'static,
+    {
+        // SAFETY: The function and the values are `Send` and `'static`. The
+        // coroutine is first resumed by a continuation of `T`.
+        let func = move |co, payload| func(co, unsafe { Co::receive(payload) });
+        unsafe { Co::spawn_unchecked(func, self) }
+    }
+
+    /// Call the target function with current continuation, passing values of
+    /// `T` alongside every transfer of the control flow.
+    ///
+    /// Besides the continuation transferred back to, the value sent alongside
+    /// is returned, if any. See [`Co::resume_with_value`] for more
+    /// information.
+    pub fn callcc_typed<T, F>(
+        self,
+        func: F,
+    ) -> Result<(Option<Co<T>>, Option<T>), NewError>
+    where
+        T: Send + 'static,
+        P: PanicHook<T>,
+        F: FnOnce(Co<T>) -> Co<T> + Send + 'static,
+    {
+        // SAFETY: The function and the values are `Send` and `'static`. The
+        // payload is sent by a continuation of `T` right after the transfer.
+        unsafe {
+            let (co, payload) = Co::callcc_unchecked(func, self)?;
+            Ok((co, Co::receive(payload)))
+        }
+    }
+}
+
+impl<S, P: PanicHook> Builder<S, P> {
     /// Create a stackful generator, a.k.a. an asymmetric coroutine.
     ///
-    /// This structure also implements [`core::ops::Coroutine`] trait.
+    /// This structure also implements [`core::ops::Coroutine`] trait. Unlike
+    /// symmetric coroutines, generators can be created on stacks borrowed for
+    /// `'a`, such as [`BorrowedStack`](unico_stack::BorrowedStack).
     pub fn r#gen<'a, F, C, Y, R>(self, func: F) -> Result<Gn<'a, C, Y, R>, NewError>
     where
+        S: IntoStack<'a>,
         F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + Send + 'a,
     {
         self.build(func)
@@ -169,6 +291,10 @@ impl<S: Into<Stack>, P: PanicHook> Builder<S, P> {
 /// Create a symmetric stackful coroutine.
 ///
 /// Unlike [`callcc`], the function will not be executed upon creation.
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::spawn`].
 pub fn spawn<F>(func: F) -> Co
 where
     F: FnOnce(Option<Co>) -> Co + Send + 'static,
@@ -178,6 +304,10 @@ where
 
 /// Like [`spawn`], but leave some checks on the function to the caller.
 ///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::spawn_unchecked`].
+///
 /// # Safety
 ///
 /// - `func` must be [`Send`], or the caller must not send the coroutine to
@@ -195,9 +325,13 @@ where
 /// Create a symmetric stackful coroutine on a specific stack.
 ///
 /// Unlike [`callcc`], the function will not be executed upon creation.
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::spawn`].
 pub fn spawn_on<S, F>(stack: S, func: F) -> Co
 where
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     F: FnOnce(Option<Co>) -> Co + Send + 'static,
 {
     Builder::new()
@@ -208,6 +342,10 @@ where
 
 /// Like [`spawn_on`], but leave some checks on the function to the caller.
 ///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::spawn_unchecked`].
+///
 /// # Safety
 ///
 /// - `func` must be [`Send`], or the caller must not send the coroutine to
@@ -216,7 +354,7 @@ where
 ///   [`Co`] not escape the lifetime of the function.
 pub unsafe fn spawn_unchecked_on<S, F>(stack: S, func: F) -> Co
 where
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     F: FnOnce(Option<Co>) -> Co,
 {
     let builder = Builder::new().on(stack);
@@ -229,6 +367,10 @@ where
 ///
 /// This function creates a symmetric stackful coroutine and immediately resume
 /// it once.
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::callcc`].
 pub fn callcc<F>(func: F) -> Option<Co>
 where
     F: FnOnce(Co) -> Co + Send + 'static,
@@ -238,6 +380,10 @@ where
 
 /// Like [`callcc`], but leave some checks on the function to the caller.
 ///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::callcc_unchecked`].
+///
 /// # Safety
 ///
 /// - `func` must be [`Send`], or the caller must not send the coroutine to
@@ -256,9 +402,13 @@ where
 ///
 /// This function creates a symmetric stackful coroutine and immediately resume
 /// it once.
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::callcc`].
 pub fn callcc_on<S, F>(stack: S, func: F) -> Option<Co>
 where
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     F: FnOnce(Co) -> Co + Send + 'static,
 {
     Builder::new()
@@ -269,6 +419,10 @@ where
 
 /// Like [`callcc_on`], but leave some checks on the function to the caller.
 ///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::callcc_unchecked`].
+///
 /// # Safety
 ///
 /// - `func` must be [`Send`], or the caller must not send the coroutine to
@@ -277,7 +431,7 @@ where
 ///   [`Co`] not escape the lifetime of the function.
 pub unsafe fn callcc_unchecked_on<S, F>(stack: S, func: F) -> Option<Co>
 where
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     F: FnOnce(Co) -> Co,
 {
     let builder = Builder::new().on(stack);
@@ -289,9 +443,13 @@ where
 /// stack.
 ///
 /// This structure also implements [`core::ops::Coroutine`] trait.
+///
+/// # Panics
+///
+/// Panics on the errors of the `gen` method of [`Builder`].
 pub fn gen_on<'a, S, F, C, Y, R>(stack: S, func: F) -> Gn<'a, C, Y, R>
 where
-    S: Into<Stack>,
+    S: IntoStack<'a>,
     F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + Send + 'a,
 {
     Builder::new()
@@ -303,6 +461,10 @@ where
 /// Create a stackful generator, a.k.a. an asymmetric coroutine.
 ///
 /// This structure also implements [`core::ops::Coroutine`] trait.
+///
+/// # Panics
+///
+/// Panics on the errors of the `gen` method of [`Builder`].
 pub fn r#gen<'a, F, C, Y, R>(func: F) -> Gn<'a, C, Y, R>
 where
     F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + Send + 'a,
@@ -0,0 +1,241 @@
+//! Growing the stack on demand for deep recursion.
+//!
+//! Coroutines usually run on small stacks, which deep recursion, like parsing
+//! or walking trees, easily overflows. Similar to
+//! [stacker](https://docs.rs/stacker), [`maybe_grow`] checks the space left on
+//! the current stack, and runs the function on a freshly allocated stack if
+//! it's running low:
+//!
+//! ```
+//! # #![feature(allocator_api)]
+//! # #![feature(coroutine_trait)]
+//! # unico_stack::global_stack_allocator!(std::alloc::Global);
+//! # unico_context::global_resumer!(unico_context::DefaultResumer);
+//! use core::ops::CoroutineState;
+//!
+//! use unico_ful::{asym::YieldHandle, grow::maybe_grow};
+//!
+//! fn depth(n: u64) -> u64 {
+//!     maybe_grow(16 * 1024, 256 * 1024, || match n {
+//!         0 => 0,
+//!         n => depth(n - 1) + 1,
+//!     })
+//! }
+//!
+//! // Far deeper than the default stack of 24 KiB allows.
+//! let mut gn = unico_ful::r#gen(|_: &mut YieldHandle, ()| depth(100_000));
+//! assert!(matches!(gn.resume(()), CoroutineState::Complete(100_000)));
+//! ```
+//!
+//! The remaining space is known for the stacks of coroutines running on their
+//! own stacks, and for the root stacks of threads on Linux, Android, Apple
+//! platforms and Windows. The contexts emulated with threads run on the root
+//! stacks of their threads.
+
+use core::{alloc::Layout, cell::Cell, ops::CoroutineState};
+
+use unico_stack::{DEFAULT_LAYOUT, Global, IntoStack};
+
+use crate::{
+    BuildUnchecked, Builder, NewError,
+    asym::{Gn, YieldHandle},
+    sym::{PanicHook, fiber},
+};
+
+/// The lowest address of the root stack of the current thread, or 0 if not
+/// queried yet.
+#[thread_local]
+static ROOT_LIMIT: Cell<usize> = Cell::new(0);
+
+/// Queries the lowest usable address of the root stack of the current thread.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn root_limit() -> Option<usize> {
+    use core::{mem::MaybeUninit, ptr};
+
+    let mut attr = MaybeUninit::<libc::pthread_attr_t>::uninit();
+    // SAFETY: `attr` is initialized by `pthread_getattr_np` if it succeeds, and
+    // destroyed right after use.
+    unsafe {
+        if libc::pthread_getattr_np(libc::pthread_self(), attr.as_mut_ptr()) != 0 {
+            return None;
+        }
+        let (mut addr, mut size, mut guard) = (ptr::null_mut(), 0, 0);
+        let ret = libc::pthread_attr_getstack(attr.as_ptr(), &mut addr, &mut size);
+        // The guard region is included in the stack reported by glibc.
+        libc::pthread_attr_getguardsize(attr.as_ptr(), &mut guard);
+        libc::pthread_attr_destroy(attr.as_mut_ptr());
+        (ret == 0).then(|| addr.addr() + guard)
+    }
+}
+
+/// Queries the lowest usable address of the root stack of the current thread.
+#[cfg(target_vendor = "apple")]
+fn root_limit() -> Option<usize> {
+    // SAFETY: The current thread is always valid.
+    unsafe {
+        let thread = libc::pthread_self();
+        let top = libc::pthread_get_stackaddr_np(thread).addr();
+        Some(top - libc::pthread_get_stacksize_np(thread))
+    }
+}
+
+/// Queries the lowest usable address of the root stack of the current thread.
+#[cfg(windows)]
+fn root_limit() -> Option<usize> {
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetCurrentThreadStackLimits(low: *mut usize, high: *mut usize);
+    }
+
+    let (mut low, mut high) = (0, 0);
+    // SAFETY: Both pointers are valid for writes.
+    unsafe { GetCurrentThreadStackLimits(&mut low, &mut high) };
+    Some(low)
+}
+
+/// Queries the lowest usable address of the root stack of the current thread.
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_vendor = "apple",
+    windows
+)))]
+fn root_limit() -> Option<usize> {
+    None
+}
+
+/// The number of bytes left on the current stack, or `None` if unknown.
+///
+/// The current stack is the one of the running coroutine if it runs on its own
+/// stack, or the root stack of the current thread otherwise. The guard region
+/// below the stack, if any, is not counted.
+#[inline(never)]
+pub fn remaining_stack() -> Option<usize> {
+    let limit = match fiber::limit() {
+        Some(limit) => limit,
+        None => match ROOT_LIMIT.get() {
+            0 => {
+                let limit = root_limit()?;
+                ROOT_LIMIT.set(limit);
+                limit
+            }
+            limit => limit,
+        },
+    };
+    Some(fiber::stack_pointer().saturating_sub(limit))
+}
+
+impl<S, P: PanicHook> Builder<S, P> {
+    /// Runs `func` to completion on the stack of the builder, returning its
+    /// value.
+    ///
+    /// The panic of `func` is propagated to the caller.
+    pub fn grow<'a, F, R>(self, func: F) -> Result<R, NewError>
+    where
+        S: IntoStack<'a>,
+        F: FnOnce() -> R + 'a,
+    {
+        let func = move |_: &mut YieldHandle<(), ()>, ()| func();
+        // SAFETY: The generator is resumed to completion on the current thread
+        // before this function returns, so `func` need neither be `Send` nor
+        // outlive the call.
+        let mut gn: Gn<'a, R> = unsafe { Gn::build_unchecked(self, func) }?;
+        match gn.resume(()) {
+            CoroutineState::Complete(ret) => Ok(ret),
+            CoroutineState::Yielded(()) => unreachable!("the function never yields"),
+        }
+    }
+}
+
+/// Runs `func` to completion on a new stack of `stack_size` bytes, returning
+/// its value.
+///
+/// The stack is allocated by the global stack allocator, and the panic of
+/// `func` is propagated to the caller.
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::grow`].
+pub fn grow<F, R>(stack_size: usize, func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let layout = Layout::from_size_align(stack_size, DEFAULT_LAYOUT.align())
+        .expect("invalid stack size");
+    Builder::new()
+        .on((&Global, layout))
+        .grow(func)
+        .expect("failed to grow the stack")
+}
+
+/// Runs `func` on the current stack if at least `red_zone` bytes are left on
+/// it, or on a new stack of `stack_size` bytes otherwise, returning its value.
+///
+/// `func` runs on the current stack as well if the space left is
+/// [unknown](remaining_stack).
+///
+/// # Panics
+///
+/// Panics on the errors of [`Builder::grow`].
+#[inline]
+pub fn maybe_grow<F, R>(red_zone: usize, stack_size: usize, func: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    match remaining_stack() {
+        Some(remaining) if remaining < red_zone => grow(stack_size, func),
+        _ => func(),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use core::{hint::black_box, ops::CoroutineState};
+
+    use super::{maybe_grow, remaining_stack};
+    use crate::{Builder, asym::YieldHandle, sym::AbortHook};
+
+    /// Recurses `n` times with some locals in each frame, returning the least
+    /// space ever left on the stacks.
+    fn recurse(n: usize) -> usize {
+        maybe_grow(16 * 1024, 64 * 1024, || {
+            let local = black_box([n; 32]);
+            let remaining = remaining_stack().unwrap();
+            match local[0] {
+                0 => remaining,
+                n => recurse(n - 1).min(remaining),
+            }
+        })
+    }
+
+    #[test]
+    fn root() {
+        assert!(remaining_stack().unwrap() > 0);
+        assert!(recurse(10_000) > 0);
+    }
+
+    /// Recurses deeply in a generator created by `builder`, whose resumer runs
+    /// coroutines on their stacks if `on_stack`.
+    pub(crate) fn coroutine(
+        builder: Builder<&'static unico_stack::Global, AbortHook>,
+        on_stack: bool,
+    ) {
+        let mut gn = builder
+            .r#gen(move |_: &mut YieldHandle, ()| {
+                let remaining = remaining_stack().unwrap();
+                if on_stack {
+                    assert!(remaining < unico_stack::DEFAULT_LAYOUT.size());
+                }
+                recurse(10_000)
+            })
+            .unwrap();
+        assert!(matches!(gn.resume(()), CoroutineState::Complete(min) if min > 0));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[should_panic = "deep"]
+    fn panicked() {
+        super::grow(64 * 1024, || panic!("deep"));
+    }
+}
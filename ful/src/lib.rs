@@ -5,8 +5,10 @@
 #![deny(trivial_casts)]
 #![deny(trivial_numeric_casts)]
 #![feature(ptr_alignment_type)]
-#![feature(allocator_api)]
+#![cfg_attr(test, feature(allocator_api))]
 #![feature(coroutine_trait)]
+#![cfg_attr(feature = "asan", feature(sanitize))]
+#![cfg_attr(any(unico_fiber, feature = "shared"), feature(thread_local))]
 
 macro_rules! ct {
     ($e:expr) => {
@@ -19,13 +21,17 @@ macro_rules! ct {
 
 pub mod asym;
 mod builder;
+#[cfg(feature = "grow")]
+pub mod grow;
+#[cfg(feature = "shared")]
+pub mod shared;
 pub mod sym;
 
-use core::alloc::{AllocError, Layout};
+use core::{alloc::Layout, fmt};
 
 pub use crate::builder::*;
 
-#[cfg(any(feature = "unwind", feature = "std"))]
+#[cfg(any(feature = "unwind", feature = "std", feature = "shared"))]
 extern crate alloc;
 
 #[cfg(any(test, feature = "std"))]
@@ -33,8 +39,43 @@ extern crate std;
 
 #[derive(Debug)]
 pub enum NewError {
-    StackTooSmall { expected: Layout, actual: Layout },
-    Context(AllocError),
+    StackTooSmall {
+        expected: Layout,
+        actual: Layout,
+    },
+    Context(unico_context::NewError),
+    /// The stack of the layout failed to be allocated, e.g. when a limit of
+    /// the stack allocator is exceeded.
+    Alloc(Layout),
+}
+
+impl fmt::Display for NewError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewError::StackTooSmall { expected, actual } => write!(
+                f,
+                "the stack is too small: expected at least {} bytes, got {} bytes",
+                expected.size(),
+                actual.size(),
+            ),
+            NewError::Context(err) => write!(f, "failed to create the context: {err}"),
+            NewError::Alloc(layout) => write!(
+                f,
+                "failed to allocate a stack of {} bytes aligned to {}",
+                layout.size(),
+                layout.align(),
+            ),
+        }
+    }
+}
+
+impl core::error::Error for NewError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            NewError::Context(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(all(not(feature = "std"), feature = "unwind"))]
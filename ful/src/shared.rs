@@ -0,0 +1,557 @@
+//! Generators sharing one execution stack.
+//!
+//! Every [`Gn`] owns a dedicated stack large enough for its deepest call, so
+//! the number of live generators is bounded by the memory of their stacks.
+//! Generators created on a [`SharedStack`] run on the same memory instead: only
+//! one of them occupies the stack at a time, while the used parts of the others
+//! are copied out to right-sized buffers on the heap, and copied back when they
+//! are resumed again, just like the "share stack" mode of
+//! [libco](https://github.com/Tencent/libco).
+//!
+//! ```
+//! # #![feature(allocator_api)]
+//! # #![feature(coroutine_trait)]
+//! # unico_stack::global_stack_allocator!(std::alloc::Global);
+//! # unico_context::global_resumer!(unico_context::DefaultResumer);
+//! use core::ops::CoroutineState;
+//!
+//! use unico_ful::{Builder, asym::YieldHandle, shared::SharedStack};
+//!
+//! let stack = SharedStack::default();
+//! let mut gens = (0..1000)
+//!     .map(|i| {
+//!         let func = move |y: &mut YieldHandle<usize, usize>, _| y.yield_(i) + i;
+//!         Builder::new().on(&stack).gen_shared(func).unwrap()
+//!     })
+//!     .collect::<Vec<_>>();
+//!
+//! for (i, gn) in gens.iter_mut().enumerate() {
+//!     assert!(matches!(gn.resume(0), CoroutineState::Yielded(x) if x == i));
+//! }
+//! for (i, gn) in gens.iter_mut().enumerate() {
+//!     assert!(matches!(gn.resume(i), CoroutineState::Complete(x) if x == i * 2));
+//! }
+//! ```
+//!
+//! Resuming a generator other than the last one costs copying the used parts
+//! of both, so this mode trades throughput for memory, and suits a large
+//! number of mostly idle generators with shallow stacks. The used part of a
+//! suspended generator is only known if its resumer saves the context record
+//! at the stack pointer, like Boost and the native backend; the whole stack is
+//! copied for the others, such as the ones based on `ucontext`.
+//!
+//! # Pointers into the shared stack
+//!
+//! The memory of a suspended generator is moved whenever another one occupies
+//! the stack, so pointers into it must not be used until it's resumed again.
+//! The safe API upholds this:
+//!
+//! - Yielded, returned and resumed values cannot borrow the locals of the
+//!   generator, since their types outlive its function.
+//! - Generators on a shared stack cannot be sent to other threads.
+//! - Creating or resuming a generator on a shared stack while another one on
+//!   the same stack is running, which would move the stack of the running
+//!   generator, panics instead. Dropping a suspended generator in such case
+//!   leaks the variables on its stack instead of unwinding them.
+
+use alloc::{rc::Rc, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    fmt,
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::{Coroutine, CoroutineState},
+    pin::Pin,
+    ptr,
+};
+
+use unico_stack::Stack;
+
+use crate::{
+    Build, BuildUnchecked, Builder, NewError,
+    asym::{Gn, YieldHandle},
+    sym::{PanicHook, fiber},
+};
+
+/// The used part of the stack of some generator.
+#[derive(Default)]
+struct Slot {
+    /// The lowest address of the used part.
+    sp: Cell<usize>,
+    /// The used part copied out, if the generator doesn't occupy the stack.
+    saved: RefCell<Vec<MaybeUninit<u8>>>,
+}
+
+struct Inner {
+    stack: Stack,
+    /// The generator whose used part is on the stack.
+    occupant: Cell<Option<Rc<Slot>>>,
+    /// Whether the occupant is running.
+    running: Cell<bool>,
+    /// The total size of the used parts copied out.
+    saved: Cell<usize>,
+}
+
+impl Inner {
+    fn top(&self) -> usize {
+        self.stack.base().addr().get() + self.stack.layout().size()
+    }
+
+    /// A stack on the same memory, which is never freed.
+    fn view(&self) -> Stack {
+        // SAFETY: The memory is valid until `self` is dropped, which outlives all
+        // the generators on it.
+        unsafe { self.stack.view() }
+    }
+
+    /// Moves the used part of `slot` onto the stack, saving the one of the
+    /// current occupant.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the occupant is running.
+    fn enter(&self, slot: &Rc<Slot>) {
+        assert!(
+            !self.running.get(),
+            "cannot switch a shared stack while a generator is running on it"
+        );
+        match self.occupant.take() {
+            Some(occupant) if Rc::ptr_eq(&occupant, slot) => {
+                self.occupant.set(Some(occupant));
+                return;
+            }
+            Some(occupant) => self.save(&occupant),
+            None => {}
+        }
+        self.restore(slot);
+        self.occupant.set(Some(slot.clone()));
+    }
+
+    fn save(&self, slot: &Slot) {
+        let sp = slot.sp.get();
+        let len = self.top() - sp;
+        let mut saved = slot.saved.borrow_mut();
+        saved.clear();
+        if saved.capacity() > len * 2 {
+            saved.shrink_to(len);
+        }
+        saved.reserve_exact(len);
+        let src = self.stack.base().as_ptr().with_addr(sp);
+        fiber::unpoison(src, len);
+        // SAFETY: The used part is within the stack, and the buffer is reserved
+        // above.
+        unsafe {
+            ptr::copy_nonoverlapping(src.cast(), saved.as_mut_ptr(), len);
+            saved.set_len(len);
+        }
+        self.saved.set(self.saved.get() + len);
+    }
+
+    fn restore(&self, slot: &Slot) {
+        let mut saved = slot.saved.borrow_mut();
+        let dst = self.stack.base().as_ptr().with_addr(slot.sp.get());
+        fiber::unpoison(dst, saved.len());
+        // SAFETY: The used part is saved from the same place in the stack, which
+        // is not occupied by others.
+        unsafe { ptr::copy_nonoverlapping(saved.as_ptr(), dst.cast(), saved.len()) };
+        self.saved.set(self.saved.get() - saved.len());
+        saved.clear();
+    }
+
+    /// Records the used part of the occupant, which is just suspended at the
+    /// context `cx`.
+    fn suspend(&self, slot: &Slot, cx: Option<usize>) {
+        let base = self.stack.base().addr().get();
+        let sp = fiber::sp_from();
+        // Boost and the native backend save the context record right at the
+        // stack pointer, below all the frames of the switch. Otherwise, e.g. if
+        // the record is on top of the stack, or the generator doesn't run on
+        // the stack at all, the whole stack is kept instead.
+        let sp = match cx {
+            Some(cx) if (base..self.top()).contains(&sp) && (base..sp).contains(&cx) => {
+                cx
+            }
+            _ => base,
+        };
+        slot.sp.set(sp);
+    }
+
+    /// Discards the used part of a finished generator.
+    fn leave(&self, slot: &Rc<Slot>) {
+        match self.occupant.take() {
+            Some(occupant) if Rc::ptr_eq(&occupant, slot) => {}
+            occupant => {
+                self.occupant.set(occupant);
+                let mut saved = slot.saved.borrow_mut();
+                self.saved.set(self.saved.get() - saved.len());
+                saved.clear();
+            }
+        }
+    }
+
+    /// Marks the occupant as running until the guard is dropped.
+    fn run(&self) -> Running<'_> {
+        self.running.set(true);
+        Running(self)
+    }
+}
+
+struct Running<'a>(&'a Inner);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.running.set(false);
+    }
+}
+
+/// A stack shared by [generators](SharedGn) created on it.
+///
+/// See [the module-level documentation](self) for more information.
+pub struct SharedStack {
+    inner: Rc<Inner>,
+}
+
+impl SharedStack {
+    /// Creates a shared stack running generators on `stack`.
+    ///
+    /// The stack should be large enough for the deepest call of all the
+    /// generators, since it's shared by them.
+    pub fn new(stack: Stack) -> Self {
+        SharedStack {
+            inner: Rc::new(Inner {
+                stack,
+                occupant: Cell::new(None),
+                running: Cell::new(false),
+                saved: Cell::new(0),
+            }),
+        }
+    }
+
+    /// The total size of the used parts of the suspended generators, copied
+    /// out to the heap.
+    pub fn saved_bytes(&self) -> usize {
+        self.inner.saved.get()
+    }
+}
+
+/// Creates a shared stack on [`Stack::default`], which may be too small for
+/// deep generators, and may not be guarded against overflows. Use
+/// [`SharedStack::new`] with a larger guarded stack for them instead.
+impl Default for SharedStack {
+    fn default() -> Self {
+        Self::new(Stack::default())
+    }
+}
+
+impl fmt::Debug for SharedStack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedStack")
+            .field("base", &self.inner.stack.base())
+            .field("layout", &self.inner.stack.layout())
+            .field("saved_bytes", &self.saved_bytes())
+            .finish()
+    }
+}
+
+/// A generator running on a [`SharedStack`].
+///
+/// This type behaves the same as [`Gn`], except that it's not [`Send`].
+pub struct SharedGn<'a, C, Y = (), R = ()> {
+    gn: ManuallyDrop<Gn<'a, C, Y, R>>,
+    slot: Rc<Slot>,
+    stack: Rc<Inner>,
+}
+
+impl<'a, F, C, Y, R, P> Build<F, &SharedStack, P> for SharedGn<'a, C, Y, R>
+where
+    F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + 'a,
+    P: PanicHook,
+{
+    fn build(builder: Builder<&SharedStack, P>, arg: F) -> Result<Self, Self::Error> {
+        // SAFETY: `arg` is `'a`, and the generator is not `Send`.
+        unsafe { Self::build_unchecked(builder, arg) }
+    }
+}
+
+impl<'a, F, C, Y, R, P> BuildUnchecked<F, &SharedStack, P> for SharedGn<'a, C, Y, R>
+where
+    F: FnOnce(&mut YieldHandle<Y, R>, R) -> C,
+    P: PanicHook,
+{
+    type Error = NewError;
+
+    /// # Safety
+    ///
+    /// `func` must be at least `'a`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if another generator on the same stack is running.
+    unsafe fn build_unchecked(
+        builder: Builder<&SharedStack, P>,
+        func: F,
+    ) -> Result<Self, Self::Error> {
+        let stack = builder.stack.inner.clone();
+        let slot = Rc::new(Slot::default());
+        stack.enter(&slot);
+
+        let res = {
+            let _running = stack.run();
+            let builder = builder.on(stack.view());
+            // SAFETY: The generator is not `Send`, and the memory of the stack
+            // outlives it.
+            unsafe { Gn::build_unchecked(builder, func) }
+        };
+        match res {
+            Ok(gn) => {
+                stack.suspend(&slot, gn.context());
+                Ok(SharedGn {
+                    gn: ManuallyDrop::new(gn),
+                    slot,
+                    stack,
+                })
+            }
+            Err(err) => {
+                stack.leave(&slot);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<C, Y, R> SharedGn<'_, C, Y, R> {
+    /// Resumes the generator, moving its stack back if necessary.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the generator is complete, or another generator on the same
+    /// stack is running.
+    pub fn resume(&mut self, resumed: R) -> CoroutineState<Y, C> {
+        self.stack.enter(&self.slot);
+        let state = {
+            let _running = self.stack.run();
+            self.gn.resume(resumed)
+        };
+        match state {
+            CoroutineState::Yielded(_) => {
+                self.stack.suspend(&self.slot, self.gn.context())
+            }
+            CoroutineState::Complete(_) => self.stack.leave(&self.slot),
+        }
+        state
+    }
+}
+
+impl<C, Y, R> Coroutine<R> for SharedGn<'_, C, Y, R> {
+    type Yield = Y;
+    type Return = C;
+
+    fn resume(mut self: Pin<&mut Self>, arg: R) -> CoroutineState<Y, C> {
+        (*self).resume(arg)
+    }
+}
+
+impl<C, Y, R> Drop for SharedGn<'_, C, Y, R> {
+    fn drop(&mut self) {
+        if self.gn.is_complete() {
+            // SAFETY: The generator is no longer used.
+            unsafe { ManuallyDrop::drop(&mut self.gn) };
+        } else if !self.stack.running.get() {
+            self.stack.enter(&self.slot);
+            let _running = self.stack.run();
+            // SAFETY: The generator is no longer used, and its stack is moved
+            // back for unwinding.
+            unsafe { ManuallyDrop::drop(&mut self.gn) };
+        }
+        // Otherwise, the generator is leaked, since the running one cannot be
+        // moved for unwinding.
+        self.stack.leave(&self.slot);
+    }
+}
+
+impl<P: PanicHook> Builder<&SharedStack, P> {
+    /// Create a stackful generator on a [`SharedStack`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if another generator on the same stack is running.
+    pub fn gen_shared<'a, F, C, Y, R>(
+        self,
+        func: F,
+    ) -> Result<SharedGn<'a, C, Y, R>, NewError>
+    where
+        F: FnOnce(&mut YieldHandle<Y, R>, R) -> C + 'a,
+    {
+        self.build(func)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    #[cfg(all(unix, not(miri)))]
+    use core::{alloc::Layout, ptr::NonNull};
+    use core::{cell::Cell, hint::black_box, ops::CoroutineState};
+    use std::{rc::Rc, vec::Vec};
+
+    #[cfg(all(unix, not(miri)))]
+    use unico_context::{Capabilities, DefaultResumer, Entry, Map, Resume, Transfer};
+    #[cfg(all(unix, not(miri)))]
+    use unico_stack::{MmapStackAllocator, Stack};
+
+    use super::{SharedGn, SharedStack};
+    use crate::{Builder, asym::YieldHandle, sym::AbortHook};
+
+    fn spawn<'a>(
+        builder: Builder<&'static unico_stack::Global, AbortHook>,
+        stack: &SharedStack,
+        id: usize,
+    ) -> SharedGn<'a, usize, usize, usize> {
+        let func = move |y: &mut YieldHandle<usize, usize>, mut r| {
+            // Locals on the stack must survive being moved.
+            let mut local = black_box([id; 64]);
+            for _ in 0..10 {
+                local.iter_mut().for_each(|x| *x += r);
+                r = y.yield_(local.iter().sum::<usize>() / 64);
+            }
+            local[0]
+        };
+        builder.on(stack).gen_shared(func).unwrap()
+    }
+
+    /// Interleaves generators on `stack`, which are created by `builder`.
+    pub(crate) fn interleaved(
+        builder: fn() -> Builder<&'static unico_stack::Global, AbortHook>,
+        stack: SharedStack,
+    ) {
+        let mut gens = (0..100)
+            .map(|id| spawn(builder(), &stack, id))
+            .collect::<Vec<_>>();
+        assert!(stack.saved_bytes() > 0);
+
+        let mut expected = (0..100).collect::<Vec<_>>();
+        for _ in 0..10 {
+            for (gn, x) in gens.iter_mut().zip(&mut expected) {
+                *x += 1;
+                assert!(matches!(gn.resume(1), CoroutineState::Yielded(y) if y == *x));
+            }
+        }
+        for (gn, x) in gens.iter_mut().zip(&expected) {
+            assert!(matches!(gn.resume(0), CoroutineState::Complete(y) if y == *x));
+        }
+        assert_eq!(stack.saved_bytes(), 0);
+    }
+
+    /// A resumer with a large frame in every switch, below the frames of the
+    /// suspending generator.
+    #[derive(Debug, Clone, Copy)]
+    #[cfg(all(unix, not(miri)))]
+    struct Deep<R>(R);
+
+    // SAFETY: Every operation is forwarded to `R`.
+    #[cfg(all(unix, not(miri)))]
+    unsafe impl<R: Resume> Resume for Deep<R> {
+        type Context = R::Context;
+
+        type NewError = R::NewError;
+
+        fn capabilities(&self) -> Capabilities {
+            self.0.capabilities()
+        }
+
+        unsafe fn new_on(
+            &self,
+            stack: NonNull<[u8]>,
+            entry: Entry<R::Context>,
+        ) -> Result<NonNull<R::Context>, R::NewError> {
+            // SAFETY: The contract is the same.
+            unsafe { self.0.new_on(stack, entry) }
+        }
+
+        #[inline(never)]
+        unsafe fn resume(
+            &self,
+            cx: NonNull<R::Context>,
+            data: *mut (),
+        ) -> Transfer<R::Context> {
+            let pad = black_box([0u8; 4096]);
+            // SAFETY: The contract is the same.
+            let t = unsafe { self.0.resume(cx, data) };
+            black_box(pad);
+            t
+        }
+
+        #[inline(never)]
+        unsafe fn resume_with(
+            &self,
+            cx: NonNull<R::Context>,
+            data: *mut (),
+            map: Map<R::Context>,
+        ) -> Transfer<R::Context> {
+            let pad = black_box([0u8; 4096]);
+            // SAFETY: The contract is the same.
+            let t = unsafe { self.0.resume_with(cx, data, map) };
+            black_box(pad);
+            t
+        }
+    }
+
+    #[test]
+    // Miri can only run the thread-emulated backend.
+    #[cfg(all(unix, not(miri)))]
+    fn deep_switch() {
+        static RESUMER: Deep<DefaultResumer> = Deep(DefaultResumer);
+        // The frames of the switches and the fiber features don't fit in the
+        // default stack, so a guarded one faults instead of corrupting the heap.
+        let layout = Layout::from_size_align(1 << 20, 4096).unwrap();
+        let stack = Stack::from((&MmapStackAllocator::new(), layout));
+        // SAFETY: All the generators in this test use the same resumer.
+        let builder = || unsafe { Builder::new().resumed_by(&RESUMER) };
+        interleaved(builder, SharedStack::new(stack));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn destruct() {
+        struct Count(Rc<Cell<usize>>);
+
+        impl Drop for Count {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let stack = SharedStack::default();
+        let count = Rc::new(Cell::new(0));
+        let mut gens = (0..10)
+            .map(|_| {
+                let count = Count(count.clone());
+                let func = move |y: &mut YieldHandle, ()| {
+                    let _count = count;
+                    y.yield_(());
+                };
+                Builder::new().on(&stack).gen_shared(func).unwrap()
+            })
+            .collect::<Vec<_>>();
+        for gn in &mut gens {
+            assert!(matches!(gn.resume(()), CoroutineState::Yielded(())));
+        }
+
+        // Every generator is moved back to the stack for unwinding.
+        drop(gens);
+        assert_eq!(count.get(), 10);
+        assert_eq!(stack.saved_bytes(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[should_panic = "cannot switch a shared stack"]
+    fn nested() {
+        let stack = Rc::new(SharedStack::default());
+        let cloned = stack.clone();
+        let func = move |_: &mut YieldHandle, ()| {
+            let mut inner = spawn(Builder::new(), &cloned, 0);
+            inner.resume(0);
+        };
+        let mut outer = Builder::new().on(&*stack).gen_shared(func).unwrap();
+        outer.resume(());
+    }
+}
@@ -1,3 +1,4 @@
+pub(crate) mod fiber;
 mod layout;
 mod raw;
 
@@ -6,14 +7,15 @@ use alloc::boxed::Box;
 #[cfg(any(feature = "unwind", feature = "std"))]
 use core::any::Any;
 use core::{
+    marker::PhantomData,
     mem::{self, ManuallyDrop},
     ptr::{self, NonNull},
 };
 
-use unico_context as cx;
-use unico_context::Transfer;
-use unico_stack::{Global, Stack};
+use unico_context::{DynResume, Transfer};
+use unico_stack::{Global, IntoStack, Stack};
 
+use self::fiber::{FakeStack, Fiber};
 pub use self::raw::{AbortHook, PanicHook, enter_root};
 use crate::{Build, BuildUnchecked, Builder, NewError};
 
@@ -22,6 +24,30 @@ use crate::{Build, BuildUnchecked, Builder, NewError};
 /// This structure represents a continuation, a.k.a. the handle of a symmetric
 /// coroutine.
 ///
+/// Values of `T` can be passed alongside every transfer of the control flow,
+/// with [`Co::resume_with_value`] and [`exit_with`]. The continuations that
+/// a continuation of `T` transfers to or receives are continuations of `T` as
+/// well, so the values sent and received always agree on their type.
+/// Continuations of `T` other than `()` are created by [`Builder::spawn_typed`]
+/// and [`Builder::callcc_typed`].
+///
+/// The values are only received by [`Co::resume_with_value`] and the functions
+/// of typed coroutines. The other methods ignore them, just like the payloads
+/// of [`Co::resume_payloaded`], and the values are dropped by their senders.
+///
+/// ```compile_fail
+/// # #![feature(allocator_api)]
+/// # unico_stack::global_stack_allocator!(std::alloc::Global);
+/// # unico_context::global_resumer!(unico_context::DefaultResumer);
+/// use unico_ful::{Builder, sym::Co};
+///
+/// let co = Builder::new()
+///     .spawn_typed(|co: Option<Co<u32>>, _| co.unwrap())
+///     .unwrap();
+/// // Continuations of `u32` only receive values of `u32`.
+/// let (_, value): (_, Option<String>) = co.resume_with_value(Some(1));
+/// ```
+///
 /// # Notes
 ///
 /// - If neither `unwind` nor `std` feature is enabled and the coroutine is
@@ -33,24 +59,96 @@ use crate::{Build, BuildUnchecked, Builder, NewError};
 ///   created by builders outside any scope of [`enter_root`], dropping the
 ///   object will result in a panic or blocking the whole control flow.
 #[derive(Debug)]
-#[repr(transparent)]
-pub struct Co {
+pub struct Co<T = ()> {
     cx: NonNull<()>,
+    rs: &'static dyn DynResume,
+    fiber: Fiber,
+    marker: PhantomData<fn(T) -> T>,
 }
 
-// SAFETY: The bounds of the actual function will be checked in the builder.
-unsafe impl Send for Co {}
-unsafe impl Sync for Co {}
-
-impl Co {
-    unsafe fn from_inner(cx: NonNull<()>) -> Self {
-        Co { cx }
+// SAFETY: The bounds of the actual function will be checked in the builder, and
+// the values passed alongside are `Send`.
+unsafe impl<T: Send> Send for Co<T> {}
+// SAFETY: No value of `T` is held. A shared reference only exposes the address
+// of the context, the resumer which is `Sync`, and the high-water mark of the
+// stack, which reads the memory through `NonNull<Stack>` under `paint`. The
+// continuation is suspended while the reference is alive, since resuming it
+// takes it by value, so its stack is not written concurrently.
+unsafe impl<T> Sync for Co<T> {}
+
+impl<T> Co<T> {
+    unsafe fn from_inner(
+        cx: NonNull<()>,
+        rs: &'static dyn DynResume,
+        fiber: Fiber,
+    ) -> Self {
+        Co {
+            cx,
+            rs,
+            fiber,
+            marker: PhantomData,
+        }
     }
 
-    fn into_inner(this: Self) -> NonNull<()> {
-        let cx = this.cx;
+    fn into_inner(this: Self) -> (NonNull<()>, &'static dyn DynResume, Fiber) {
+        let ret = (this.cx, this.rs, this.fiber);
         mem::forget(this);
-        cx
+        ret
+    }
+
+    /// The address of the suspended context.
+    #[cfg(feature = "shared")]
+    pub(crate) fn context(&self) -> usize {
+        self.cx.addr().get()
+    }
+
+    /// Changes the type of values passed alongside.
+    ///
+    /// # Safety
+    ///
+    /// The continuation must receive values of `U` if it's resumed with one,
+    /// and must not be resumed with a map function that gives back
+    /// continuations other than the ones of `U`.
+    unsafe fn cast<U>(self) -> Co<U> {
+        let (cx, rs, fiber) = Co::into_inner(self);
+        // SAFETY: The contract is the same.
+        unsafe { Co::from_inner(cx, rs, fiber) }
+    }
+
+    /// Takes the value out of `payload`, and drops the continuation exiting
+    /// with it, if any.
+    ///
+    /// # Safety
+    ///
+    /// `payload` must be either null or the packet sent by some continuation
+    /// of `T`, right after the control flow is transferred from it.
+    pub(crate) unsafe fn receive(payload: *mut ()) -> Option<T> {
+        // SAFETY: The packet is valid by contract.
+        let packet = unsafe { payload.cast::<Packet<T>>().as_mut() }?;
+        let value = packet.value.take();
+        // The packet is no longer valid after the exiting continuation is dropped.
+        drop(packet.exiting.take());
+        value
+    }
+}
+
+/// The payload of the transfers between continuations of `T`.
+struct Packet<T> {
+    value: Option<T>,
+    /// The continuation that sends the value in [`exit_with`], which is
+    /// dropped by the receiver.
+    exiting: Option<Co>,
+}
+
+/// Erases the type of continuations returned by the panic hook of a typed
+/// continuation, which are transferred to with nothing.
+struct ErasedHook<P, T>(P, PhantomData<fn() -> T>);
+
+impl<P: PanicHook<T>, T> PanicHook for ErasedHook<P, T> {
+    #[cfg(any(feature = "unwind", feature = "std"))]
+    fn rewind(self, payload: Box<dyn Any + Send>) -> Co {
+        // SAFETY: The continuation is resumed without a value and a map function.
+        unsafe { self.0.rewind(payload).cast() }
     }
 }
 
@@ -61,10 +159,29 @@ impl Co {
     }
 }
 
+impl<T> Co<T> {
+    /// The largest size of the stack of this continuation ever used, in bytes.
+    ///
+    /// Returns `None` if this continuation represents the root stack of some
+    /// thread, its stack is not [painted](Stack::with_paint), or the resumer
+    /// doesn't run coroutines on their own stacks, such as the thread-emulated
+    /// one.
+    #[cfg(feature = "paint")]
+    pub fn high_water_mark(&self) -> Option<usize> {
+        if !self.rs.capabilities().on_stack {
+            return None;
+        }
+        let stack = self.fiber.stack()?;
+        // SAFETY: The continuation is suspended, so its stack is alive and not
+        // in use.
+        unsafe { stack.as_ref() }.high_water_mark()
+    }
+}
+
 impl<F, S, P> Build<F, S, P> for Co
 where
     F: FnOnce(Option<Co>) -> Co + Send + 'static,
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     P: PanicHook,
 {
     fn build(builder: Builder<S, P>, arg: F) -> Result<Self, Self::Error> {
@@ -76,7 +193,7 @@ where
 impl<F, S, P> BuildUnchecked<F, S, P> for Co
 where
     F: FnOnce(Option<Co>) -> Co,
-    S: Into<Stack>,
+    S: IntoStack<'static>,
     P: PanicHook,
 {
     type Error = NewError;
@@ -91,32 +208,70 @@ where
         builder: Builder<S, P>,
         arg: F,
     ) -> Result<Self, Self::Error> {
-        let Builder { stack, panic_hook } = builder;
         // SAFETY: The contract is the same.
-        unsafe { raw::RawCo::new_on(stack.into(), panic_hook, arg) }
+        unsafe { Co::spawn_unchecked(move |co, _| arg(co), builder) }
     }
 }
 
-impl Co {
+impl<T> Co<T> {
     /// # Safety
     ///
     /// - `func` must be [`Send`], or the caller must not send the coroutine to
     ///   another thread.
-    /// - `arg` must be `'static`, or the caller must ensure that the returned
+    /// - `func` must be `'static`, or the caller must ensure that the returned
     ///   [`Co`] not escape the lifetime of the function.
+    /// - `T` must be [`Send`] and `'static` if `func` is required to be.
+    ///
+    /// `func` receives the payload of the first transfer to the coroutine.
+    pub(crate) unsafe fn spawn_unchecked<F, S, P>(
+        func: F,
+        builder: Builder<S, P>,
+    ) -> Result<Self, NewError>
+    where
+        F: FnOnce(Option<Self>, *mut ()) -> Self,
+        S: IntoStack<'static>,
+        P: PanicHook<T>,
+    {
+        // SAFETY: The coroutine is resumed by continuations of `T` at first.
+        let func = move |co: Option<Co>, payload| unsafe {
+            func(co.map(|co| co.cast()), payload).cast()
+        };
+        let resumer = builder.resumer();
+        let Builder {
+            stack, panic_hook, ..
+        } = builder;
+        let panic_hook = ErasedHook(panic_hook, PhantomData);
+        // SAFETY: The contract is the same. Both the coroutine and the caller
+        // only exchange values of `T`.
+        unsafe { raw::RawCo::new_on(stack, panic_hook, resumer, func) }
+            .map(|co| unsafe { co.cast() })
+    }
+
+    /// # Safety
+    ///
+    /// See [`Co::spawn_unchecked`] for more information.
+    ///
+    /// The payload of the transfer back to the caller is returned.
     pub(crate) unsafe fn callcc_unchecked<F, S, P>(
         func: F,
-        stack: S,
-        panic_hook: P,
-    ) -> Result<Option<Self>, NewError>
+        builder: Builder<S, P>,
+    ) -> Result<(Option<Self>, *mut ()), NewError>
     where
-        F: FnOnce(Co) -> Co,
-        S: Into<Stack>,
-        P: PanicHook,
+        F: FnOnce(Self) -> Self,
+        S: IntoStack<'static>,
+        P: PanicHook<T>,
     {
-        let func = |opt: Option<Co>| func(opt.unwrap());
+        // SAFETY: Both the coroutine and the caller only exchange values of `T`.
+        let func = |co: Option<Co>, _| unsafe { func(co.unwrap().cast()).cast() };
+        let resumer = builder.resumer();
+        let Builder {
+            stack, panic_hook, ..
+        } = builder;
+        let panic_hook = ErasedHook(panic_hook, PhantomData);
         // SAFETY: The contract is the same.
-        unsafe { raw::RawCo::callcc_on(stack.into(), panic_hook, func) }
+        let (co, payload) =
+            unsafe { raw::RawCo::callcc_on(stack, panic_hook, resumer, func) }?;
+        Ok((co.map(|co| unsafe { co.cast() }), payload))
     }
 
     /// Transfers the current control flow to this continuation.
@@ -129,6 +284,28 @@ impl Co {
         unsafe { self.resume_payloaded(ptr::null_mut()).0 }
     }
 
+    /// Similar to [`Co::resume`], but sends `value` alongside if any, and
+    /// returns the value that the control flow is transferred back with, if
+    /// any.
+    ///
+    /// The value is dropped here if the continuation ignores it.
+    pub fn resume_with_value(self, value: Option<T>) -> (Option<Self>, Option<T>) {
+        let mut packet = Packet {
+            value,
+            exiting: None,
+        };
+        let payload = match packet.value {
+            Some(_) => ptr::from_mut(&mut packet).cast(),
+            None => ptr::null_mut(),
+        };
+        // SAFETY: The packet is received right after the transfer, during
+        // which the current stack is suspended. So does the one sent back.
+        unsafe {
+            let (co, payload) = self.resume_payloaded(payload);
+            (co, Self::receive(payload))
+        }
+    }
+
     /// Similar to [`Co::resume`], but maps the source of this continuation,
     /// a.k.a. the caller of this method, to another one if possible.
     ///
@@ -160,8 +337,12 @@ impl Co {
     /// The validity of returned pointer is not guaranteed whether `payload` is
     /// valid. The caller must maintains this manually, usually by calling this
     /// function in pairs.
+    ///
+    /// `payload` must be null if the continuation is waiting in
+    /// [`Co::resume_with_value`], or is a fresh coroutine created with values
+    /// of `T`, which regard other payloads as the values sent alongside.
     pub unsafe fn resume_payloaded(self, payload: *mut ()) -> (Option<Self>, *mut ()) {
-        let cx = Co::into_inner(self);
+        let (cx, rs, fiber) = Co::into_inner(self);
         // SAFETY: `cx`'s lifetime is bound to its own coroutine, and it is ALWAYS
         // THE UNIQUE REFERENCE to the runtime stack. The proof is divided into 2
         // points:
@@ -195,10 +376,16 @@ impl Co {
         //
         //    Thus, though the naming of variables will be a bit rough, the statement
         // actually proves to be true.
-        let Transfer { context, data } = unsafe { cx::resume(cx, payload) };
+        let mut fake = FakeStack::new();
+        fiber::start(Some(&mut fake), fiber);
+        let Transfer { context, data } = unsafe { rs.resume(cx, payload) };
+        let from = fiber::finish(fake);
 
         // SAFETY: `cx` is valid by contract.
-        (context.map(|cx| unsafe { Co::from_inner(cx) }), data)
+        (
+            context.map(|cx| unsafe { Co::from_inner(cx, rs, from) }),
+            data,
+        )
     }
 
     /// Similar to [`Co::resume_with`], but with a possibly-returned pointer
@@ -209,34 +396,47 @@ impl Co {
     /// The validity of returned pointer is not guaranteed whether `payload` is
     /// valid. The caller must maintains this manually, usually by calling this
     /// function in pairs.
+    ///
+    /// See [`Co::resume_payloaded`] for the payloads returned by `map`.
     pub unsafe fn resume_payloaded_with<M>(self, map: M) -> (Option<Self>, *mut ())
     where
         M: FnOnce(Self) -> (Option<Self>, *mut ()),
     {
-        let cx = Co::into_inner(self);
+        let (cx, rs, fiber) = Co::into_inner(self);
 
-        let mut data = ManuallyDrop::new(map);
+        let mut data = ManuallyDrop::new(raw::MapData { func: map, rs });
         let ptr = ptr::from_mut(&mut data).cast();
 
+        let mut fake = FakeStack::new();
+        fiber::start(Some(&mut fake), fiber);
         // SAFETY: The proof is the same as the one in `Co::resume_payloaded`.
         let Transfer { context, data } =
-            unsafe { cx::resume_with(cx, ptr, raw::map::<M>) };
+            unsafe { rs.resume_with(cx, ptr, raw::map::<T, M>) };
+        let from = fiber::finish(fake);
 
         // SAFETY: `cx` is valid by contract.
-        (context.map(|cx| unsafe { Co::from_inner(cx) }), data)
+        (
+            context.map(|cx| unsafe { Co::from_inner(cx, rs, from) }),
+            data,
+        )
     }
 }
 
-impl Drop for Co {
+impl<T> Drop for Co<T> {
     fn drop(&mut self) {
         #[allow(unused_variables)]
         // SAFETY： We don't use `self.cx`any longer after taking out data from these
-        // fields. The safety proof of `cx::resume_with` is the same as the one in
+        // fields. The safety proof of `DynResume::resume_with` is the same as the one in
         // `Co::resume_payloaded`.
         unsafe {
-            let cx = self.cx;
+            let (cx, rs) = (self.cx, self.rs);
             #[cfg(any(feature = "unwind", feature = "std"))]
-            cx::resume_with(cx, ptr::null_mut(), raw::unwind);
+            {
+                let mut fake = FakeStack::new();
+                fiber::start(Some(&mut fake), self.fiber);
+                rs.resume_with(cx, raw::resumer_ptr(&rs), raw::unwind);
+                fiber::finish(fake);
+            }
         }
     }
 }
@@ -249,13 +449,43 @@ impl Drop for Co {
 ///
 /// This is basically a shorthand for `next.resume_with(|_| None)`, but reduces
 /// some overhead if unwinding is enabled.
-pub fn exit(next: Co) -> ! {
+pub fn exit<T>(next: Co<T>) -> ! {
+    // SAFETY: `next` is transferred to without a value.
+    let next = unsafe { next.cast::<()>() };
     #[cfg(any(feature = "unwind", feature = "std"))]
-    raw::unwind(Co::into_inner(next), ptr::null_mut());
+    raw::exit(next);
     #[cfg(not(any(feature = "unwind", feature = "std")))]
-    next.resume_with(|_| None);
+    {
+        next.resume_with(|_| None);
+        unreachable!("Exiting failed. There's at least some dangling `Co` instance!")
+    }
+}
 
-    unreachable!("Exiting failed. There's at least some dangling `Co` instance!")
+/// Like [`exit`], but sends `value` to `next` alongside.
+///
+/// The current continuation is dropped by `next` right after `value` is
+/// received, so all the variables on the current call stack will be safely
+/// dropped if unwinding is enabled. Otherwise, the current stack is leaked, as
+/// described in the notes of [`Co`].
+///
+/// If `next` ignores the value, e.g. waiting in [`Co::resume`], both the value
+/// and the current continuation are leaked.
+pub fn exit_with<T>(next: Co<T>, value: T) -> ! {
+    let mut packet = Packet {
+        value: Some(value),
+        exiting: None,
+    };
+    let payload = ptr::from_mut(&mut packet);
+    let map = move |co: Co<T>| {
+        // SAFETY: The current stack is suspended during the transfer, and the
+        // packet is received right after it. The exiting continuation is only
+        // dropped.
+        unsafe { (*payload).exiting = Some(co.cast()) };
+        (None, payload.cast())
+    };
+    // SAFETY: See above.
+    unsafe { next.resume_payloaded_with(map) };
+    unreachable!("Exiting failed. The exited continuation is resumed again!")
 }
 
 /// Resume the unwinding for this coroutine's partial destruction process. Use
@@ -275,97 +505,371 @@ pub fn handle_exit(payload: Box<dyn Any + Send>) -> Box<dyn Any + Send> {
 
 #[cfg(test)]
 mod tests {
-    use core::convert::identity;
-    use std::{alloc::Global, string::String};
+    use std::alloc::Global;
 
-    use unico_context::{boost::Boost, global_resumer};
+    use unico_context::global_resumer;
     use unico_stack::global_stack_allocator;
 
-    use crate::{callcc, spawn, spawn_unchecked, sym::exit};
-
     global_stack_allocator!(Global);
-    global_resumer!(Boost);
+    // Miri can only run the thread-emulated backend.
+    #[cfg(not(miri))]
+    global_resumer!(unico_context::DefaultResumer);
+    #[cfg(miri)]
+    global_resumer!(unico_context::thread::Thread);
 
-    #[test]
-    fn creation() {
-        spawn(Option::unwrap);
+    /// Runs the same tests with coroutines switched by the resumer `$rs`.
+    ///
+    /// The checked resumers skip the tests of shared stacks, since they can't
+    /// tell apart the generators suspended at the same address.
+    macro_rules! suite {
+        (@common $rs:expr) => {
+            use core::convert::identity;
+            use std::string::String;
+
+            use crate::{
+                Builder,
+                sym::{AbortHook, Co, exit, exit_with},
+            };
+
+            fn builder() -> Builder<&'static unico_stack::Global, AbortHook> {
+                // SAFETY: All the coroutines in this module use the same resumer.
+                unsafe { Co::builder().resumed_by($rs) }
+            }
+
+            fn spawn<F>(func: F) -> Co
+            where
+                F: FnOnce(Option<Co>) -> Co + Send + 'static,
+            {
+                builder().spawn(func).unwrap()
+            }
+
+            unsafe fn spawn_unchecked<F>(func: F) -> Co
+            where
+                F: FnOnce(Option<Co>) -> Co,
+            {
+                unsafe { builder().spawn_unchecked(func) }.unwrap()
+            }
+
+            fn callcc<F>(func: F) -> Option<Co>
+            where
+                F: FnOnce(Co) -> Co + Send + 'static,
+            {
+                builder().callcc(func).unwrap()
+            }
+
+            #[test]
+            fn creation() {
+                spawn(Option::unwrap);
+            }
+
+            #[test]
+            fn empty() {
+                assert!(callcc(identity).is_none());
+            }
+
+            #[test]
+            fn panicked() {
+                std::println!("0");
+                callcc(|co| {
+                    let ret = builder()
+                        .hook_panic_with(move |_| {
+                            std::println!("3");
+                            co
+                        })
+                        .spawn(|_| panic!("2"));
+                    std::println!("1");
+                    ret.unwrap()
+                });
+                std::println!("4");
+            }
+
+            #[test]
+            fn capture_move() {
+                let s = String::from("hello");
+                let ret = callcc(move |co| {
+                    assert_eq!(s, "hello");
+                    exit(co)
+                });
+                assert!(ret.is_none());
+            }
+
+            #[test]
+            fn capture_ref() {
+                let mut counter = 0;
+                let mut co = unsafe {
+                    spawn_unchecked(|mut co| {
+                        for _ in 0..10 {
+                            counter += 1;
+                            co = co.unwrap().resume();
+                        }
+                        co.unwrap()
+                    })
+                };
+                loop {
+                    co = match co.resume() {
+                        Some(co) => co,
+                        None => break,
+                    }
+                }
+                assert_eq!(counter, 10);
+            }
+
+            #[test]
+            fn symmetric() {
+                let b = spawn(|a| {
+                    let c = spawn(move |b| {
+                        std::println!("3");
+                        let ret = b.unwrap().resume();
+                        std::println!("5");
+                        assert!(ret.is_none());
+                        a.unwrap()
+                    });
+                    std::println!("2");
+                    let ret = c.resume().unwrap();
+                    std::println!("4");
+                    ret
+                });
+                std::println!("1");
+                let ret = b.resume();
+                std::println!("6");
+                assert!(ret.is_none());
+            }
+
+            #[test]
+            fn symmetric_direct() {
+                assert!(callcc(|a| spawn(move |_| a)).is_none());
+            }
+
+            #[test]
+            fn typed() {
+                let func = |co: Option<Co<usize>>, value: Option<usize>| {
+                    let (mut co, mut value) = (co.unwrap(), value.unwrap());
+                    loop {
+                        match co.resume_with_value(Some(value * 2)) {
+                            (Some(next), Some(received)) => {
+                                (co, value) = (next, received)
+                            }
+                            (Some(next), None) => break next,
+                            (None, _) => unreachable!(),
+                        }
+                    }
+                };
+                let co = builder().spawn_typed(func).unwrap();
+                let (co, value) = co.resume_with_value(Some(1));
+                assert_eq!(value, Some(2));
+                let (co, value) = co.unwrap().resume_with_value(Some(21));
+                assert_eq!(value, Some(42));
+                let (co, value) = co.unwrap().resume_with_value(None);
+                assert!(co.is_none() && value.is_none());
+            }
+
+            #[test]
+            fn untyped_payload() {
+                let co = spawn(|co| {
+                    let mut payload = 42usize;
+                    let payload = core::ptr::from_mut(&mut payload).cast();
+                    // SAFETY: The payload is ignored by `Co::resume`.
+                    let (co, _) = unsafe { co.unwrap().resume_payloaded(payload) };
+                    co.unwrap()
+                });
+                let co = co.resume().unwrap();
+                assert!(co.resume().is_none());
+            }
+
+            #[test]
+            fn typed_exit() {
+                let count = std::sync::Arc::new(());
+                let cloned = count.clone();
+                let (co, value) = builder()
+                    .callcc_typed(move |co| {
+                        let _count = cloned;
+                        exit_with(co, String::from("exited"))
+                    })
+                    .unwrap();
+                assert!(co.is_none());
+                assert_eq!(value.as_deref(), Some("exited"));
+                // The exited stack is unwound.
+                #[cfg(feature = "std")]
+                assert_eq!(std::sync::Arc::strong_count(&count), 1);
+            }
+
+            #[test]
+            fn stack_too_small() {
+                let min = builder().capabilities().min_stack;
+                let stack = core::alloc::Layout::from_size_align(min.size(), 16).unwrap();
+                let ret = builder().on(stack).spawn(Option::unwrap);
+                assert!(matches!(ret, Err(crate::NewError::StackTooSmall { .. })));
+
+                // The layout is rejected before the allocation, which would fail.
+                let none = unico_stack::Limited::new(std::alloc::Global).max_stacks(0);
+                let ret = builder().on((&none, stack)).spawn(Option::unwrap);
+                assert!(matches!(ret, Err(crate::NewError::StackTooSmall { .. })));
+            }
+
+            #[test]
+            // Miri doesn't support `mmap`.
+            #[cfg(all(unix, not(miri)))]
+            fn mmap_stack() {
+                let alloc = unico_stack::MmapStackAllocator::new();
+                let co = builder()
+                    .on((&alloc, unico_stack::DEFAULT_LAYOUT))
+                    .spawn(Option::unwrap)
+                    .unwrap();
+                assert!(co.resume().is_none());
+            }
+
+            #[test]
+            fn static_pool() {
+                static POOL: unico_stack::StaticStackPool<2, 32768> =
+                    unico_stack::StaticStackPool::new();
+                let co = builder().on(&POOL).spawn(Option::unwrap).unwrap();
+                assert_eq!(POOL.available(), 1);
+                assert!(co.resume().is_none());
+                assert_eq!(POOL.available(), 2);
+            }
+
+            #[test]
+            fn limited() {
+                static LIMITED: unico_stack::Limited<std::alloc::Global> =
+                    unico_stack::Limited::new(std::alloc::Global).max_stacks(1);
+                let builder = || builder().on((&LIMITED, unico_stack::DEFAULT_LAYOUT));
+                let co = builder().spawn(Option::unwrap).unwrap();
+                let ret = builder().spawn(Option::unwrap);
+                assert!(matches!(ret, Err(crate::NewError::Alloc(_))));
+                assert_eq!(LIMITED.usage().stacks, 1);
+
+                assert!(co.resume().is_none());
+                assert_eq!(LIMITED.usage().stacks, 0);
+                let co = builder().spawn(Option::unwrap).unwrap();
+                assert!(co.resume().is_none());
+            }
+
+            #[test]
+            #[cfg(feature = "grow")]
+            fn grow() {
+                let rs: &'static dyn unico_context::DynResume = $rs;
+                crate::grow::tests::coroutine(builder(), rs.capabilities().on_stack);
+            }
+        };
+        ($rs:expr, checked) => {
+            suite!(@common $rs);
+        };
+        ($rs:expr) => {
+            suite!(@common $rs);
+
+            #[test]
+            #[cfg(feature = "shared")]
+            fn shared() {
+                crate::shared::tests::interleaved(builder, crate::shared::SharedStack::default());
+            }
+        };
     }
 
-    #[test]
-    fn empty() {
-        assert!(callcc(identity).is_none());
+    mod global {
+        suite!(&unico_context::Global);
     }
 
-    #[test]
-    fn panicked() {
-        std::println!("0");
-        callcc(|co| {
-            let ret = super::Co::builder()
-                .hook_panic_with(move |_| {
-                    std::println!("3");
-                    co
-                })
-                .spawn(|_| panic!("2"));
-            std::println!("1");
-            ret.unwrap()
-        });
-        std::println!("4");
+    #[cfg(all(unix, not(miri)))]
+    mod ucx {
+        suite!(&unico_context::ucx::Ucontext);
     }
 
-    #[test]
-    fn capture_move() {
-        let s = String::from("hello");
-        let ret = callcc(move |co| {
-            assert_eq!(s, "hello");
-            exit(co)
-        });
-        assert!(ret.is_none());
+    #[cfg(all(
+        any(target_arch = "x86_64", target_arch = "aarch64"),
+        not(windows),
+        not(miri)
+    ))]
+    mod native {
+        suite!(&unico_context::native::Native);
     }
 
-    #[test]
-    fn capture_ref() {
-        let mut counter = 0;
-        let mut co = unsafe {
-            spawn_unchecked(|mut co| {
-                for _ in 0..10 {
-                    counter += 1;
-                    co = co.unwrap().resume();
-                }
-                co.unwrap()
-            })
-        };
-        loop {
-            co = match co.resume() {
-                Some(co) => co,
-                None => break,
-            }
-        }
-        assert_eq!(counter, 10);
+    mod thread {
+        suite!(&unico_context::thread::Thread);
+    }
+
+    mod checked {
+        use unico_context::checked::Checked;
+
+        #[cfg(not(miri))]
+        suite!(&Checked(unico_context::DefaultResumer), checked);
+        #[cfg(miri)]
+        suite!(&Checked(unico_context::thread::Thread), checked);
     }
 
     #[test]
-    fn symmetric() {
-        let b = spawn(|a| {
-            let c = spawn(move |b| {
-                std::println!("3");
-                let ret = b.unwrap().resume();
-                std::println!("5");
-                assert!(ret.is_none());
-                a.unwrap()
-            });
-            std::println!("2");
-            let ret = c.resume().unwrap();
-            std::println!("4");
-            ret
-        });
-        std::println!("1");
-        let ret = b.resume();
-        std::println!("6");
-        assert!(ret.is_none());
+    // The thread-emulated backend doesn't run coroutines on their stacks.
+    #[cfg(all(feature = "paint", not(miri)))]
+    fn high_water_mark() {
+        use std::{alloc::Global, hint::black_box};
+
+        use unico_stack::{DEFAULT_LAYOUT, Painted};
+
+        use crate::sym::Co;
+
+        let painted = Painted::new(Global);
+        let co = Co::builder()
+            .on((&painted, DEFAULT_LAYOUT))
+            .spawn(|main| {
+                let main = main.unwrap();
+                // The root stack is not measured.
+                assert_eq!(main.high_water_mark(), None);
+                let buf = black_box([1u8; 4096]);
+                let main = main.resume().unwrap();
+                black_box(buf);
+                main
+            })
+            .unwrap();
+        let fresh = co.high_water_mark().unwrap();
+        let co = co.resume().unwrap();
+        assert!(co.high_water_mark().unwrap() >= fresh + 4096);
+        assert!(co.resume().is_none());
+
+        let histogram = painted.histogram();
+        assert_eq!(histogram.count(), 1);
+        assert!(histogram.max() >= fresh + 4096);
     }
 
+    /// Overflows a coroutine in a child process, which should be reported by
+    /// the handler before aborting.
     #[test]
-    fn symmetric_direct() {
-        assert!(callcc(|a| spawn(move |_| a)).is_none());
+    #[cfg(all(unix, feature = "overflow", not(miri)))]
+    fn overflow() {
+        use std::{env, hint::black_box, process::Command, string::String};
+
+        use crate::sym::Co;
+
+        const CHILD: &str = "UNICO_FUL_OVERFLOW_CHILD";
+
+        fn recurse(depth: usize) -> usize {
+            let buf = black_box([depth as u8; 1024]);
+            match depth {
+                usize::MAX => 0,
+                _ => black_box(recurse(depth + 1)) + usize::from(buf[depth % 1024]),
+            }
+        }
+
+        if env::var_os(CHILD).is_some() {
+            unico_stack::overflow::install();
+            let alloc = unico_stack::MmapStackAllocator::new();
+            let co = Co::builder()
+                .on((&alloc, unico_stack::DEFAULT_LAYOUT))
+                .spawn(|co| {
+                    recurse(0);
+                    co.unwrap()
+                })
+                .unwrap();
+            co.resume();
+            unreachable!("the coroutine should have overflowed");
+        }
+
+        let output = Command::new(env::current_exe().unwrap())
+            .args(["sym::tests::overflow", "--exact", "--nocapture"])
+            .env(CHILD, "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("stack overflow in coroutine"), "{stderr}");
+        assert!(stderr.contains("overflow::{{closure}}"), "{stderr}");
     }
 }
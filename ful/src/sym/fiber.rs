@@ -0,0 +1,449 @@
+//! Bookkeeping of the running stack across switches.
+//!
+//! Every switch is surrounded by [`start`] and [`finish`], which keep track of
+//! the stack in use for the features that need it:
+//!
+//! - AddressSanitizer (the `asan` feature) needs the bounds of the stacks;
+//! - ThreadSanitizer (the `tsan` feature) needs a fiber for each stack;
+//! - Valgrind (the `valgrind` feature) needs each stack to be registered;
+//! - the stack overflow handler (the `overflow` feature) needs the guard region
+//!   of the running stack;
+//! - measuring the high-water marks of stacks (the `paint` feature) needs the
+//!   stack of each [`Co`](super::Co);
+//! - copying the used part of shared stacks (the `shared` feature) needs the
+//!   stack pointer of the suspended context;
+//! - querying the remaining space of the running stack (the `grow` feature)
+//!   needs its lowest address.
+//!
+//! The [`Fiber`] of the target is carried by every [`Co`](super::Co), and the
+//! fiber of the previous stack is reported when the switch finishes. Contexts
+//! not running on their own stacks have no fibers.
+//!
+//! The build script sets `cfg(unico_fiber)` if any of the features except
+//! `shared` is enabled, which needs no fibers. All the functions are no-ops if
+//! none of the features are enabled.
+
+#[cfg(any(unico_fiber, feature = "shared"))]
+use core::cell::Cell;
+#[cfg(any(feature = "asan", feature = "tsan"))]
+use core::ffi::c_void;
+#[cfg(any(
+    feature = "asan",
+    feature = "tsan",
+    feature = "shared",
+    feature = "grow"
+))]
+use core::ptr;
+#[cfg(feature = "paint")]
+use core::ptr::NonNull;
+
+use unico_stack::Stack;
+#[cfg(feature = "overflow")]
+use unico_stack::overflow::{self, GuardRegion};
+
+#[cfg(feature = "asan")]
+unsafe extern "C" {
+    fn __sanitizer_start_switch_fiber(
+        fake_stack_save: *mut *mut c_void,
+        bottom: *const c_void,
+        size: usize,
+    );
+
+    fn __sanitizer_finish_switch_fiber(
+        fake_stack_save: *mut c_void,
+        bottom_old: *mut *const c_void,
+        size_old: *mut usize,
+    );
+
+    #[cfg(feature = "shared")]
+    fn __asan_unpoison_memory_region(addr: *const c_void, size: usize);
+}
+
+#[cfg(feature = "tsan")]
+unsafe extern "C" {
+    fn __tsan_get_current_fiber() -> *mut c_void;
+
+    fn __tsan_create_fiber(flags: u32) -> *mut c_void;
+
+    fn __tsan_destroy_fiber(fiber: *mut c_void);
+
+    fn __tsan_switch_to_fiber(fiber: *mut c_void, flags: u32);
+}
+
+#[cfg(feature = "valgrind")]
+mod valgrind {
+    const STACK_REGISTER: usize = 0x1501;
+    const STACK_DEREGISTER: usize = 0x1502;
+
+    /// Issues a Valgrind client request, returning `default` if the program is
+    /// not running on Valgrind.
+    #[inline]
+    fn request(default: usize, args: [usize; 6]) -> usize {
+        let mut ret = default;
+        // SAFETY: The magic sequence is a no-op if not running on Valgrind.
+        #[cfg(target_arch = "x86_64")]
+        unsafe {
+            core::arch::asm!(
+                "rol rdi, 3",
+                "rol rdi, 13",
+                "rol rdi, 61",
+                "rol rdi, 51",
+                "xchg rbx, rbx",
+                inout("rdx") ret,
+                in("rax") args.as_ptr(),
+                options(nostack),
+            );
+        }
+        // SAFETY: The magic sequence is a no-op if not running on Valgrind.
+        #[cfg(target_arch = "aarch64")]
+        unsafe {
+            core::arch::asm!(
+                "ror x12, x12, #3",
+                "ror x12, x12, #13",
+                "ror x12, x12, #51",
+                "ror x12, x12, #61",
+                "orr x10, x10, x10",
+                inout("x3") ret,
+                in("x4") args.as_ptr(),
+                options(nostack),
+            );
+        }
+        #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+        let _ = args;
+        ret
+    }
+
+    /// Registers the stack `[start, end)`, returning its ID.
+    ///
+    /// Returns `usize::MAX` if the program is not running on Valgrind.
+    pub fn stack_register(start: usize, end: usize) -> usize {
+        request(usize::MAX, [STACK_REGISTER, start, end, 0, 0, 0])
+    }
+
+    pub fn stack_deregister(id: usize) {
+        request(0, [STACK_DEREGISTER, id, 0, 0, 0, 0]);
+    }
+}
+
+/// The identity of some stack known by sanitizers.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Fiber {
+    #[cfg(feature = "asan")]
+    bottom: usize,
+    #[cfg(feature = "asan")]
+    size: usize,
+    #[cfg(feature = "tsan")]
+    tsan: *mut c_void,
+    #[cfg(feature = "valgrind")]
+    valgrind: usize,
+    #[cfg(feature = "overflow")]
+    overflow: Option<GuardRegion>,
+    #[cfg(feature = "paint")]
+    stack: Option<NonNull<Stack>>,
+    #[cfg(feature = "grow")]
+    limit: usize,
+    /// Whether the fiber runs on its own stack on the current thread, or the
+    /// switches to it are no-ops.
+    #[cfg(unico_fiber)]
+    on_stack: bool,
+}
+
+impl Fiber {
+    /// The fiber of a context not running on its own stack, such as the
+    /// thread-emulated one, which no sanitizer knows about.
+    const OFF_STACK: Fiber = Fiber {
+        #[cfg(feature = "asan")]
+        bottom: 0,
+        #[cfg(feature = "asan")]
+        size: 0,
+        #[cfg(feature = "tsan")]
+        tsan: ptr::null_mut(),
+        #[cfg(feature = "valgrind")]
+        valgrind: usize::MAX,
+        #[cfg(feature = "overflow")]
+        overflow: None,
+        #[cfg(feature = "paint")]
+        stack: None,
+        #[cfg(feature = "grow")]
+        limit: 0,
+        #[cfg(unico_fiber)]
+        on_stack: false,
+    };
+
+    /// Creates a new fiber for a fresh stack running the coroutine named
+    /// `name`, which should be [destroyed] after the stack is no longer used.
+    ///
+    /// `stack` should stay in place until the fiber is destroyed, and
+    /// `on_stack` tells whether the coroutine actually runs on it. If not,
+    /// nothing is annotated when switching to the fiber.
+    ///
+    /// [destroyed]: Fiber::destroy
+    #[cfg_attr(not(feature = "overflow"), allow(unused_variables))]
+    pub fn new(stack: &Stack, on_stack: bool, name: &'static str) -> Self {
+        if !on_stack {
+            return Fiber::OFF_STACK;
+        }
+        Fiber {
+            #[cfg(feature = "asan")]
+            bottom: stack.base().addr().get(),
+            #[cfg(feature = "asan")]
+            size: stack.layout().size(),
+            // SAFETY: The fiber is destroyed in `destroy`.
+            #[cfg(feature = "tsan")]
+            tsan: unsafe { __tsan_create_fiber(0) },
+            #[cfg(feature = "valgrind")]
+            valgrind: {
+                let start = stack.base().addr().get();
+                valgrind::stack_register(start, start + stack.layout().size())
+            },
+            #[cfg(feature = "overflow")]
+            overflow: GuardRegion::new(stack, name),
+            #[cfg(feature = "paint")]
+            stack: Some(NonNull::from(stack)),
+            #[cfg(feature = "grow")]
+            limit: stack.base().addr().get(),
+            #[cfg(unico_fiber)]
+            on_stack: true,
+        }
+    }
+
+    /// The stack of the fiber, or `None` for the root stack of a thread.
+    #[cfg(feature = "paint")]
+    pub fn stack(&self) -> Option<NonNull<Stack>> {
+        self.stack
+    }
+
+    /// Destroys the fiber of a finished stack other than the current one.
+    #[cfg_attr(not(feature = "valgrind"), allow(unused_variables))]
+    pub fn destroy(self) {
+        // SAFETY: The fiber is created in `new` and not in use.
+        #[cfg(feature = "tsan")]
+        if self.on_stack {
+            unsafe { __tsan_destroy_fiber(self.tsan) }
+        }
+        #[cfg(feature = "valgrind")]
+        if self.valgrind != usize::MAX {
+            valgrind::stack_deregister(self.valgrind)
+        }
+    }
+}
+
+/// The fake stack of the current context, saved across a switch when
+/// `detect_stack_use_after_return` is on.
+#[derive(Debug)]
+pub(super) struct FakeStack {
+    #[cfg(feature = "asan")]
+    ptr: *mut c_void,
+}
+
+impl FakeStack {
+    pub fn new() -> Self {
+        FakeStack {
+            #[cfg(feature = "asan")]
+            ptr: ptr::null_mut(),
+        }
+    }
+}
+
+/// The fiber that switched to the current one, or `None` if the switch is not
+/// finished yet.
+///
+/// A thread that never started a switch, e.g. one emulating some context, is
+/// switched to from off the stack.
+#[cfg(unico_fiber)]
+#[thread_local]
+static FROM: Cell<Option<Fiber>> = Cell::new(Some(Fiber::OFF_STACK));
+
+/// The TSan fiber before the last switch.
+#[cfg(feature = "tsan")]
+#[thread_local]
+static TSAN_FROM: Cell<*mut c_void> = Cell::new(ptr::null_mut());
+
+/// The Valgrind stack ID of the current stack.
+///
+/// The root stacks of threads are never registered, nor destroyed, so their
+/// IDs are left as a placeholder.
+#[cfg(feature = "valgrind")]
+#[thread_local]
+static VALGRIND_CURRENT: Cell<usize> = Cell::new(usize::MAX);
+
+/// The Valgrind stack ID before the last switch.
+#[cfg(feature = "valgrind")]
+#[thread_local]
+static VALGRIND_FROM: Cell<usize> = Cell::new(usize::MAX);
+
+/// The guard region of the stack before the last switch.
+#[cfg(feature = "overflow")]
+#[thread_local]
+static OVERFLOW_FROM: Cell<Option<GuardRegion>> = Cell::new(None);
+
+/// The stack of the current fiber, or `None` for the root stack of the thread.
+#[cfg(feature = "paint")]
+#[thread_local]
+static STACK_CURRENT: Cell<Option<NonNull<Stack>>> = Cell::new(None);
+
+/// The stack before the last switch.
+#[cfg(feature = "paint")]
+#[thread_local]
+static STACK_FROM: Cell<Option<NonNull<Stack>>> = Cell::new(None);
+
+/// The lowest address of the current stack, or 0 for the root stack of the
+/// thread.
+#[cfg(feature = "grow")]
+#[thread_local]
+static LIMIT_CURRENT: Cell<usize> = Cell::new(0);
+
+/// The lowest address of the stack before the last switch.
+#[cfg(feature = "grow")]
+#[thread_local]
+static LIMIT_FROM: Cell<usize> = Cell::new(0);
+
+/// The approximate stack pointer of the stack before the last switch.
+#[cfg(feature = "shared")]
+#[thread_local]
+static SP_FROM: Cell<usize> = Cell::new(0);
+
+/// Starts switching to the `target` fiber.
+///
+/// `fake` should be `None` if the current stack will never be resumed again.
+/// In this case, the fake stack of the current context is destroyed, so the
+/// caller must not be instrumented by ASan.
+///
+/// Nothing is annotated if `target` doesn't run on its own stack, and the
+/// switch is finished immediately from off the stack.
+///
+/// This function is always inlined so that neither the fake stack nor the
+/// shadow call stack of the target fiber sees its return.
+#[inline(always)]
+#[cfg_attr(not(feature = "asan"), allow(unused_variables))]
+pub(super) fn start(fake: Option<&mut FakeStack>, target: Fiber) {
+    #[cfg(unico_fiber)]
+    {
+        if !target.on_stack {
+            FROM.set(Some(Fiber::OFF_STACK));
+            return;
+        }
+        FROM.set(None);
+    }
+    #[cfg(feature = "valgrind")]
+    VALGRIND_FROM.set(VALGRIND_CURRENT.replace(target.valgrind));
+    #[cfg(feature = "overflow")]
+    OVERFLOW_FROM.set(overflow::set_current(target.overflow));
+    #[cfg(feature = "paint")]
+    STACK_FROM.set(STACK_CURRENT.replace(target.stack));
+    #[cfg(feature = "grow")]
+    LIMIT_FROM.set(LIMIT_CURRENT.replace(target.limit));
+    #[cfg(feature = "shared")]
+    SP_FROM.set(stack_pointer());
+    #[cfg(feature = "tsan")]
+    // SAFETY: `target` is a valid fiber, and the switch is performed right after.
+    unsafe {
+        TSAN_FROM.set(__tsan_get_current_fiber());
+        __tsan_switch_to_fiber(target.tsan, 0);
+    }
+    #[cfg(feature = "asan")]
+    {
+        let fake = fake.map_or(ptr::null_mut(), |fake| ptr::from_mut(&mut fake.ptr));
+        let bottom = ptr::without_provenance(target.bottom);
+        // SAFETY: `target` is a valid stack.
+        unsafe { __sanitizer_start_switch_fiber(fake, bottom, target.size) }
+    }
+}
+
+/// Finishes the switch to the current stack, returning the fiber of the
+/// previous one.
+///
+/// This function can be called more than once after a switch, e.g. once in
+/// some `map` function and once after `resume_with` returns. Only the first
+/// call restores `fake`.
+#[inline]
+#[cfg_attr(not(feature = "asan"), allow(unused_variables))]
+pub(super) fn finish(fake: FakeStack) -> Fiber {
+    #[cfg(unico_fiber)]
+    if let Some(from) = FROM.get() {
+        return from;
+    }
+    #[cfg(feature = "asan")]
+    let (mut bottom, mut size) = (ptr::null(), 0);
+    // SAFETY: The switch is started by `start`.
+    #[cfg(feature = "asan")]
+    unsafe {
+        __sanitizer_finish_switch_fiber(fake.ptr, &mut bottom, &mut size)
+    };
+    let from = Fiber {
+        #[cfg(feature = "asan")]
+        bottom: bottom.addr(),
+        #[cfg(feature = "asan")]
+        size,
+        #[cfg(feature = "tsan")]
+        tsan: TSAN_FROM.get(),
+        #[cfg(feature = "valgrind")]
+        valgrind: VALGRIND_FROM.get(),
+        #[cfg(feature = "overflow")]
+        overflow: OVERFLOW_FROM.get(),
+        #[cfg(feature = "paint")]
+        stack: STACK_FROM.get(),
+        #[cfg(feature = "grow")]
+        limit: LIMIT_FROM.get(),
+        #[cfg(unico_fiber)]
+        on_stack: true,
+    };
+    #[cfg(unico_fiber)]
+    FROM.set(Some(from));
+    from
+}
+
+/// Overrides the result of the following [`finish`]es, if the continuation
+/// passed to the current stack is not the previous one, e.g. returned from a
+/// `map` function.
+#[inline]
+#[cfg_attr(not(unico_fiber), allow(unused_variables))]
+pub(super) fn set_from(from: Fiber) {
+    #[cfg(unico_fiber)]
+    FROM.set(Some(from));
+}
+
+/// An address right below the frame of the caller.
+///
+/// The marker is never moved to the fake stack of ASan.
+#[cfg(any(feature = "shared", feature = "grow"))]
+#[inline(never)]
+#[cfg_attr(feature = "asan", sanitize(address = "off"))]
+pub(crate) fn stack_pointer() -> usize {
+    let marker = 0u8;
+    ptr::from_ref(&marker).addr()
+}
+
+/// Marks `len` bytes at `ptr` on some stack as addressable, so that the frames
+/// in them can be copied around.
+///
+/// The redzones of the frames are poisoned by ASan until they return, which
+/// doesn't happen in place for the frames moved out of a shared stack.
+#[cfg(feature = "shared")]
+#[inline]
+#[cfg_attr(not(feature = "asan"), allow(unused_variables))]
+pub(crate) fn unpoison(ptr: *const u8, len: usize) {
+    // SAFETY: The memory is valid, and not used by any running frame.
+    #[cfg(feature = "asan")]
+    unsafe {
+        __asan_unpoison_memory_region(ptr.cast(), len)
+    }
+}
+
+/// The approximate stack pointer of the stack that switched to the current
+/// one, i.e. an address right below the frame that started the last switch.
+///
+/// The frames of the switch itself are below the returned address.
+#[cfg(feature = "shared")]
+#[inline]
+pub(crate) fn sp_from() -> usize {
+    SP_FROM.get()
+}
+
+/// The lowest address of the current stack, or `None` for the root stack of
+/// the thread, including the ones of contexts emulated with threads.
+#[cfg(feature = "grow")]
+#[inline]
+pub(crate) fn limit() -> Option<usize> {
+    Some(LIMIT_CURRENT.get()).filter(|&limit| limit != 0)
+}
@@ -9,10 +9,15 @@ use core::{
     ptr::{self, NonNull},
 };
 
-use unico_context::{self as cx, Transfer};
+use unico_context::{self as cx, DynResume, Transfer};
+use unico_stack::IntoStack;
 
 pub use self::panicking::*;
-use super::{Co, NewError, Stack, layout::extend};
+use super::{
+    Co, NewError, Stack,
+    fiber::{self, FakeStack, Fiber},
+    layout::extend,
+};
 #[cfg(any(feature = "unwind", feature = "std"))]
 use crate::unwind;
 
@@ -21,12 +26,14 @@ struct Layouts {
     offset_stack: usize,
     offset_func: usize,
     offset_hook: usize,
+    offset_resumer: usize,
 }
 
 pub(crate) struct RawCo<F, P: PanicHook> {
     stack: *mut Stack,
     func: *mut F,
     panic_hook: *mut P,
+    resumer: *mut &'static dyn DynResume,
 }
 
 impl<F, P: PanicHook> RawCo<F, P> {
@@ -34,11 +41,13 @@ impl<F, P: PanicHook> RawCo<F, P> {
         let func = Layout::new::<F>();
         let stack = Layout::new::<Stack>();
         let hook = Layout::new::<P>();
+        let resumer = Layout::new::<&'static dyn DynResume>();
 
         let layout = Layout::new::<()>();
         let (layout, offset_stack) = ct!(extend(layout, stack));
         let (layout, offset_func) = ct!(extend(layout, func));
         let (layout, offset_hook) = ct!(extend(layout, hook));
+        let (layout, offset_resumer) = ct!(extend(layout, resumer));
 
         assert!(offset_stack == 0);
         Some(Layouts {
@@ -46,6 +55,7 @@ impl<F, P: PanicHook> RawCo<F, P> {
             offset_stack,
             offset_func,
             offset_hook,
+            offset_resumer,
         })
     }
 
@@ -62,62 +72,85 @@ impl<F, P: PanicHook> RawCo<F, P> {
         }
     }
 
+    /// The minimum layout of stacks, with the header on top of the rest part
+    /// of the stack required by `resumer`.
+    fn min_stack(resumer: &dyn DynResume) -> Layout {
+        let min = resumer.capabilities().min_stack;
+        let layout = Self::layouts().layout;
+        // The header is placed at the top, aligned down.
+        let size = min.size().next_multiple_of(layout.align()) + layout.size();
+        Layout::from_size_align(size, min.align().max(layout.align()))
+            .expect("the minimum layout of stacks overflowed")
+    }
+
     fn from_ptr(ptr: *mut ()) -> Self {
         let layouts = Self::layouts();
         RawCo {
             stack: ptr.map_addr(|addr| addr + layouts.offset_stack).cast(),
             func: ptr.map_addr(|addr| addr + layouts.offset_func).cast(),
             panic_hook: ptr.map_addr(|addr| addr + layouts.offset_hook).cast(),
+            resumer: ptr.map_addr(|addr| addr + layouts.offset_resumer).cast(),
         }
     }
 }
 
 impl<F, P> RawCo<F, P>
 where
-    F: FnOnce(Option<Co>) -> Co,
+    F: FnOnce(Option<Co>, *mut ()) -> Co,
     P: PanicHook,
 {
     /// # Safety
     ///
     /// See `super::Builder::spawn_unchecked` for more information.
     pub(crate) unsafe fn new_on(
-        stack: Stack,
+        stack: impl IntoStack<'static>,
         panic_hook: P,
+        resumer: &'static dyn DynResume,
         func: F,
     ) -> Result<Co, NewError> {
         // SAFETY: The safety requirements is the same.
-        unsafe { Self::new_on_imp(stack, panic_hook, func, Self::entry::<false>) }
-            .map(Option::unwrap)
+        unsafe {
+            Self::new_on_imp(stack, panic_hook, resumer, func, Self::entry::<false>)
+        }
+        .map(|(co, _)| co.unwrap())
     }
 
+    /// Like [`RawCo::new_on`], but runs `func` immediately, returning the
+    /// continuation and the payload that `func` transfers back with.
     pub(crate) unsafe fn callcc_on(
-        stack: Stack,
+        stack: impl IntoStack<'static>,
         panic_hook: P,
+        resumer: &'static dyn DynResume,
         func: F,
-    ) -> Result<Option<Co>, NewError> {
+    ) -> Result<(Option<Co>, *mut ()), NewError> {
         // SAFETY: The safety requirements is the same.
-        unsafe { Self::new_on_imp(stack, panic_hook, func, Self::entry::<true>) }
+        unsafe { Self::new_on_imp(stack, panic_hook, resumer, func, Self::entry::<true>) }
     }
 
     /// # Safety
     ///
     /// - See `super::Builder::spawn_unchecked` for more information.
     pub(crate) unsafe fn new_on_imp(
-        stack: Stack,
+        stack: impl IntoStack<'static>,
         panic_hook: P,
+        resumer: &'static dyn DynResume,
         func: F,
         entry: cx::Entry<()>,
-    ) -> Result<Option<Co>, NewError> {
-        let layouts = Self::layouts();
+    ) -> Result<(Option<Co>, *mut ()), NewError> {
+        let expected = Self::min_stack(resumer);
+        let check = |actual: Layout| {
+            if actual.size() < expected.size() || actual.align() < expected.align() {
+                return Err(NewError::StackTooSmall { expected, actual });
+            }
+            Ok(())
+        };
+        // The requested layout is rejected before the allocation.
+        stack.layout().map_or(Ok(()), check)?;
+        // SAFETY: The stack is valid for `'static`, or for the coroutine by the
+        // contract.
+        let stack = unsafe { stack.into_stack() }.map_err(NewError::Alloc)?;
         let stack_layout = stack.layout();
-        if stack_layout.size() <= layouts.layout.size()
-            || stack_layout.align() < layouts.layout.align()
-        {
-            return Err(NewError::StackTooSmall {
-                expected: layouts.layout,
-                actual: stack_layout,
-            });
-        }
+        check(stack_layout)?;
 
         let (pointer, rest_size) = {
             let layouts = Self::layouts();
@@ -134,7 +167,7 @@ where
         // `Stack::new`.
         let context = unsafe {
             let ptr = NonNull::slice_from_raw_parts(stack.base(), rest_size);
-            cx::new_on(ptr, entry)
+            resumer.new_on(ptr, entry)
         }
         .map_err(NewError::Context)?;
 
@@ -145,25 +178,40 @@ where
             raw.stack.write(stack);
             raw.func.write(func);
             raw.panic_hook.write(panic_hook);
+            raw.resumer.write(resumer);
         }
+        let on_stack = resumer.capabilities().on_stack;
+        // SAFETY: The stack is written above, and stays in place until the
+        // coroutine finishes.
+        let fiber = Fiber::new(unsafe { &*raw.stack }, on_stack, type_name::<F>());
 
+        let mut fake = FakeStack::new();
+        fiber::start(Some(&mut fake), fiber);
         // SAFETY: The proof is the same as the one in `Co::resume_payloaded`.
-        let resume = unsafe { cx::resume(context, pointer) };
+        let resume = unsafe { resumer.resume(context, pointer) };
+        let from = fiber::finish(fake);
         // SAFETY: `context` is valid by contract.
-        Ok(resume.context.map(|cx| unsafe { Co::from_inner(cx) }))
+        let co = resume
+            .context
+            .map(|cx| unsafe { Co::from_inner(cx, resumer, from) });
+        Ok((co, resume.data))
     }
 }
 
 impl<F, P> RawCo<F, P>
 where
-    F: FnOnce(Option<Co>) -> Co,
+    F: FnOnce(Option<Co>, *mut ()) -> Co,
     P: PanicHook,
 {
     /// # Safety
     ///
     /// `ptr` must points to a valid `RawCo` calculated from `RawCo::from_ptr`.
+    #[cfg_attr(feature = "asan", sanitize(address = "off"))]
     unsafe extern "C" fn entry<const CALLCC: bool>(cx: NonNull<()>, ptr: *mut ()) -> ! {
+        let from = fiber::finish(FakeStack::new());
         let task = Self::from_ptr(ptr);
+        // SAFETY: The task is valid by contract.
+        let rs = unsafe { task.resumer.read() };
 
         // SAFETY: The task is valid by contract.
         #[cfg(any(feature = "unwind", feature = "std"))]
@@ -173,19 +221,28 @@ where
         let func = unsafe { task.func.read() };
 
         let run = || {
-            func(if CALLCC {
+            if CALLCC {
                 // SAFETY: `cx` is valid by contract.
-                Some(unsafe { Co::from_inner(cx) })
+                func(
+                    Some(unsafe { Co::from_inner(cx, rs, from) }),
+                    ptr::null_mut(),
+                )
             } else {
+                let mut fake = FakeStack::new();
+                fiber::start(Some(&mut fake), from);
                 // SAFETY: The proof is the same as the one in `Co::resume_payloaded`.
-                let Transfer { context, .. } = unsafe { cx::resume(cx, ptr) };
+                let Transfer { context, data } = unsafe { rs.resume(cx, ptr) };
+                let from = fiber::finish(fake);
                 // SAFETY: `cx` is valid by contract.
-                context.map(|cx| unsafe { Co::from_inner(cx) })
-            })
+                func(
+                    context.map(|cx| unsafe { Co::from_inner(cx, rs, from) }),
+                    data,
+                )
+            }
         };
 
         #[cfg(any(feature = "unwind", feature = "std"))]
-        let context = {
+        let (context, rs, fiber) = {
             // Move the hook in the braces to make sure it drops when the control flow
             // goes out of the scope.
             let rewind = |payload| AssertUnwindSafe(|| hook.rewind(payload));
@@ -212,10 +269,12 @@ where
             })
         };
         #[cfg(not(any(feature = "unwind", feature = "std")))]
-        let (context, next) = Co::into_inner(run());
+        let (context, rs, fiber) = Co::into_inner(run());
 
+        // The current stack is about to be dropped in `exit`.
+        fiber::start(None, fiber);
         // SAFETY: The proof is the same as the one in `Co::resume_payloaded`.
-        unsafe { cx::resume_with(context, ptr, Self::exit) };
+        unsafe { rs.resume_with(context, ptr, Self::exit) };
         unreachable!("Exiting failed. There's at least some dangling `Co` instance!")
     }
 
@@ -224,6 +283,8 @@ where
     /// `ptr` must points to a valid `RawCo` calculated from `RawCo::from_ptr`.
     #[allow(improper_ctypes_definitions)]
     unsafe extern "C-unwind" fn exit(_: NonNull<()>, ptr: *mut ()) -> Transfer<()> {
+        // The fiber of the exited stack.
+        fiber::finish(FakeStack::new()).destroy();
         let task = Self::from_ptr(ptr);
         // SAFETY: The task is valid by contract.
         unsafe {
@@ -238,20 +299,34 @@ where
     }
 }
 
+/// The data passed to [`map`].
+pub(super) struct MapData<M> {
+    pub func: M,
+    pub rs: &'static dyn DynResume,
+}
+
 /// # Safety
 ///
-/// `ptr` must offer a valid `M` in `TransferData`.
+/// `ptr` must offer a valid `MapData<M>` in `TransferData`.
 #[allow(improper_ctypes_definitions)]
-pub(super) unsafe extern "C-unwind" fn map<M: FnOnce(Co) -> (Option<Co>, *mut ())>(
+pub(super) unsafe extern "C-unwind" fn map<T, M>(
     cx: NonNull<()>,
     payload: *mut (),
-) -> Transfer<()> {
+) -> Transfer<()>
+where
+    M: FnOnce(Co<T>) -> (Option<Co<T>>, *mut ()),
+{
     // SAFETY: The only reading is safe by contract.
-    let func = unsafe { payload.cast::<M>().read() };
+    let MapData { func, rs } = unsafe { payload.cast::<MapData<M>>().read() };
+    let from = fiber::finish(FakeStack::new());
     // SAFETY: `cx` is valid by contract.
-    let (ret, payload) = func(unsafe { Co::from_inner(cx) });
+    let (ret, payload) = func(unsafe { Co::from_inner(cx, rs, from) });
     Transfer {
-        context: ret.map(Co::into_inner),
+        context: ret.map(|co| {
+            let (cx, _, fiber) = Co::into_inner(co);
+            fiber::set_from(fiber);
+            cx
+        }),
         data: payload,
     }
 }
@@ -267,7 +342,7 @@ pub(super) unsafe extern "C-unwind" fn map<M: FnOnce(Co) -> (Option<Co>, *mut ()
 /// ```rust,should_panic
 /// # #![feature(allocator_api)]
 /// # unico_stack::global_stack_allocator!(std::alloc::Global);
-/// # unico_context::global_resumer!(unico_context::boost::Boost);
+/// # unico_context::global_resumer!(unico_context::DefaultResumer);
 ///
 /// unico_ful::callcc(|co| {
 ///     // This statement will abort the whole thread,
@@ -286,7 +361,7 @@ pub(super) unsafe extern "C-unwind" fn map<M: FnOnce(Co) -> (Option<Co>, *mut ()
 /// ```rust
 /// # #![feature(allocator_api)]
 /// # unico_stack::global_stack_allocator!(std::alloc::Global);
-/// # unico_context::global_resumer!(unico_context::boost::Boost);
+/// # unico_context::global_resumer!(unico_context::DefaultResumer);
 ///
 /// unsafe { unico_ful::sym::enter_root(|| {
 ///     unico_ful::callcc(|co| {
@@ -313,9 +388,13 @@ pub unsafe fn enter_root<R>(f: impl FnOnce() -> R + UnwindSafe) -> R {
         Ok(ret) => ret,
         Err(payload) => match payload.downcast::<HandleDrop>() {
             Ok(data) => {
+                let (cx, rs, fiber) = Co::into_inner(data.next);
+                // The fake stack is kept since the root stack is still in use by
+                // the current thread.
+                fiber::start(Some(&mut FakeStack::new()), fiber);
                 // SAFETY: The `cx` is valid while the root control flow should be aborted
                 // immediately.
-                unsafe { cx::resume(Co::into_inner(data.next), ptr::null_mut()) };
+                unsafe { rs.resume(cx, ptr::null_mut()) };
                 unreachable!("Failed to drop the root `Co`")
             }
             Err(payload) => unwind::resume_unwind(payload),
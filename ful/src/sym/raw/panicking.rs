@@ -2,13 +2,21 @@
 use alloc::boxed::Box;
 #[cfg(any(feature = "unwind", feature = "std"))]
 use core::any::Any;
+#[cfg(any(feature = "unwind", feature = "std"))]
+use core::ptr;
 use core::ptr::NonNull;
 
 #[cfg(any(feature = "unwind", feature = "std"))]
-use unico_context::Transfer;
+use unico_context::{DynResume, Transfer};
 
 #[cfg(any(feature = "unwind", feature = "std"))]
-use crate::{sym::Co, unwind};
+use crate::{
+    sym::{
+        Co,
+        fiber::{self, FakeStack},
+    },
+    unwind,
+};
 
 #[cfg(any(feature = "unwind", feature = "std"))]
 pub(in crate::sym) struct HandleDrop {
@@ -21,15 +29,32 @@ pub(in crate::sym) struct HandleDrop {
 #[cfg(any(feature = "unwind", feature = "std"))]
 unsafe impl Send for HandleDrop {}
 
+/// Passes the resumer of the current coroutine to [`unwind`].
+#[cfg(any(feature = "unwind", feature = "std"))]
+pub(in crate::sym) fn resumer_ptr(rs: &&'static dyn DynResume) -> *mut () {
+    ptr::from_ref(rs).cast_mut().cast()
+}
+
+/// # Safety
+///
+/// `rs` must be created from [`resumer_ptr`].
 #[cfg(any(feature = "unwind", feature = "std"))]
 #[allow(improper_ctypes_definitions)]
-pub(in crate::sym) extern "C-unwind" fn unwind(
+pub(in crate::sym) unsafe extern "C-unwind" fn unwind(
     cx: NonNull<()>,
-    _: *mut (),
+    rs: *mut (),
 ) -> Transfer<()> {
-    unwind::resume_unwind(Box::new(HandleDrop {
-        next: unsafe { Co::from_inner(cx) },
-    }))
+    // SAFETY: `rs` is valid by contract.
+    let rs = unsafe { rs.cast::<&'static dyn DynResume>().read() };
+    let from = fiber::finish(FakeStack::new());
+    exit(unsafe { Co::from_inner(cx, rs, from) })
+}
+
+/// Unwinds the current call stack, and transfers the control flow to `next`
+/// at last.
+#[cfg(any(feature = "unwind", feature = "std"))]
+pub(in crate::sym) fn exit(next: Co) -> ! {
+    unwind::resume_unwind(Box::new(HandleDrop { next }))
 }
 
 #[cfg(any(feature = "unwind", feature = "std"))]
@@ -46,7 +71,10 @@ pub(in crate::sym) fn resume_unwind(payload: Box<dyn Any + Send>) -> Box<dyn Any
 /// coroutine panics, the panic payload will be handled to it, which then either
 /// gives back a continuation to be passed on, or simply aborts the whole
 /// control flow, depending on its implementation.
-pub trait PanicHook {
+///
+/// The hook of a coroutine passing values of `T` gives back a continuation of
+/// `T` as well, which is transferred to without a value.
+pub trait PanicHook<T = ()> {
     /// The actual process of handling the panic. See [`PanicHook`] for more
     /// information.
     ///
@@ -54,7 +82,7 @@ pub trait PanicHook {
     /// implements this trait. So no need to create some unit structure if the
     /// actual type is not used.
     #[cfg(any(feature = "unwind", feature = "std"))]
-    fn rewind(self, payload: Box<dyn Any + Send>) -> Co;
+    fn rewind(self, payload: Box<dyn Any + Send>) -> Co<T>;
 }
 
 /// Aborts the whole control flow if a panic is caught in the current
@@ -63,19 +91,19 @@ pub trait PanicHook {
 /// See [`PanicHook`] for more information.
 pub struct AbortHook;
 
-impl PanicHook for AbortHook {
+impl<T> PanicHook<T> for AbortHook {
     #[cfg(any(feature = "unwind", feature = "std"))]
-    fn rewind(self, _: Box<dyn Any + Send>) -> Co {
+    fn rewind(self, _: Box<dyn Any + Send>) -> Co<T> {
         unreachable!("Uncaught panic in the root of a symmetric coroutine. Aborting.")
     }
 }
 
-impl<T> PanicHook for T
+impl<F, T> PanicHook<T> for F
 where
-    T: FnOnce(Box<dyn Any + Send>) -> Co,
+    F: FnOnce(Box<dyn Any + Send>) -> Co<T>,
 {
     #[cfg(any(feature = "unwind", feature = "std"))]
-    fn rewind(self, payload: Box<dyn Any + Send>) -> Co {
+    fn rewind(self, payload: Box<dyn Any + Send>) -> Co<T> {
         self(payload)
     }
 }
@@ -18,5 +18,9 @@ pub mod asym {
     pub use unico_async::asym::*;
     pub use unico_ful::asym::*;
 }
+#[cfg(feature = "grow")]
+pub use unico_ful::grow;
+#[cfg(feature = "shared")]
+pub use unico_ful::shared;
 #[cfg(feature = "asym")]
 pub use unico_ful::{r#gen, gen_on};
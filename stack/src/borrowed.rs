@@ -0,0 +1,302 @@
+//! Stacks on memory provided by the caller, without any allocator.
+
+use core::{
+    alloc::{AllocError, Layout},
+    cell::UnsafeCell,
+    fmt,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{IntoStack, Stack, StackAllocator, forget, header_in, with_header};
+
+/// The alignment of stacks on provided memory.
+const ALIGN: usize = 16;
+
+/// Creates a stack on the aligned part of `memory`, which is never freed.
+///
+/// # Safety
+///
+/// `memory` must be valid for reads and writes until the stack is no longer
+/// used.
+pub(crate) unsafe fn stack_in(memory: NonNull<[MaybeUninit<u8>]>) -> Stack {
+    let base = memory.as_non_null_ptr().cast::<u8>();
+    let start = base.addr().get().next_multiple_of(ALIGN);
+    let end = (base.addr().get() + memory.len()) & !(ALIGN - 1);
+    let layout = Layout::from_size_align(end.saturating_sub(start), ALIGN).unwrap();
+    // SAFETY: The aligned part is within `memory`, or empty.
+    let pointer = unsafe { base.add(start.min(end) - base.addr().get()) };
+    // SAFETY: The memory is valid by contract, and is not owned by the stack.
+    unsafe { Stack::new(pointer, layout, forget) }
+}
+
+/// A stack on some memory borrowed for `'a`.
+///
+/// The stack is only convertible into a [`Stack`] through [`IntoStack<'a>`],
+/// so coroutines that may outlive `'a`, such as symmetric ones, cannot be
+/// created on it, while generators and futures bounded by `'a` can.
+///
+/// ```
+/// use core::mem::MaybeUninit;
+///
+/// use unico_stack::BorrowedStack;
+///
+/// let mut memory = [MaybeUninit::uninit(); 16384];
+/// let stack = BorrowedStack::new(&mut memory);
+/// assert!(stack.stack().layout().size() >= 16384 - 16);
+/// ```
+///
+/// The memory is aligned to 16 bytes, so that a few bytes at both ends may be
+/// left unused.
+///
+/// Only uninitialized memory is accepted. The contents of the memory are left
+/// unspecified after the coroutine finishes, or forever if the coroutine is
+/// forgotten, so initialized bytes could no longer be sound to read.
+pub struct BorrowedStack<'a> {
+    stack: Stack,
+    marker: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> BorrowedStack<'a> {
+    /// Creates a stack on `memory`.
+    pub fn new(memory: &'a mut [MaybeUninit<u8>]) -> Self {
+        BorrowedStack {
+            // SAFETY: The memory is borrowed for `'a`, and the stack is not
+            // exposed beyond that.
+            stack: unsafe { stack_in(NonNull::from(memory)) },
+            marker: PhantomData,
+        }
+    }
+
+    /// The stack on the borrowed memory.
+    pub fn stack(&self) -> &Stack {
+        &self.stack
+    }
+}
+
+impl fmt::Debug for BorrowedStack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BorrowedStack")
+            .field("base", &self.stack.base())
+            .field("layout", &self.stack.layout())
+            .finish()
+    }
+}
+
+impl<'a> From<&'a mut [MaybeUninit<u8>]> for BorrowedStack<'a> {
+    fn from(memory: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self::new(memory)
+    }
+}
+
+impl<'a, const N: usize> From<&'a mut [MaybeUninit<u8>; N]> for BorrowedStack<'a> {
+    fn from(memory: &'a mut [MaybeUninit<u8>; N]) -> Self {
+        Self::new(memory)
+    }
+}
+
+// SAFETY: The stack is only valid for `'b`, which outlives `'a`.
+unsafe impl<'a, 'b: 'a> IntoStack<'a> for BorrowedStack<'b> {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        Ok(self.stack)
+    }
+}
+
+#[repr(C, align(16))]
+struct Slot<const SIZE: usize>(UnsafeCell<[MaybeUninit<u8>; SIZE]>);
+
+/// The record of a stack lent by a [`StaticStackPool`], saved on top of its
+/// usable memory.
+type Header = &'static AtomicBool;
+
+unsafe fn release(memory: NonNull<u8>, returned: Layout) {
+    // SAFETY: The header is written in `allocate`, and never modified.
+    let used = unsafe { header_in::<Header>(memory, returned).read() };
+    used.store(false, Ordering::Release);
+}
+
+/// A fixed number of stacks of `SIZE` bytes embedded in the pool itself, which
+/// needs no allocator at all.
+///
+/// Stacks are lent by a `&'static` pool only, so the pool is usually defined
+/// as a static item:
+///
+/// ```
+/// use unico_stack::{Stack, StaticStackPool};
+///
+/// static POOL: StaticStackPool<4, 16384> = StaticStackPool::new();
+///
+/// let stack = Stack::from(&POOL);
+/// assert_eq!(POOL.available(), 3);
+/// drop(stack);
+/// assert_eq!(POOL.available(), 4);
+/// ```
+///
+/// Every stack takes a whole slot regardless of the requested size, and the
+/// allocation fails if all the slots are in use or the requested layout
+/// doesn't fit in a slot. Slots are aligned to 16 bytes, so the pool can't be
+/// installed as the global stack allocator as long as [`DEFAULT_LAYOUT`]
+/// is requested.
+///
+/// [`DEFAULT_LAYOUT`]: crate::DEFAULT_LAYOUT
+pub struct StaticStackPool<const N: usize, const SIZE: usize> {
+    slots: [Slot<SIZE>; N],
+    used: [AtomicBool; N],
+}
+
+// SAFETY: Each slot is only accessed by the stack that acquires it.
+unsafe impl<const N: usize, const SIZE: usize> Sync for StaticStackPool<N, SIZE> {}
+
+impl<const N: usize, const SIZE: usize> StaticStackPool<N, SIZE> {
+    /// The usable layout of each stack.
+    const RETURNED: Layout = match Layout::from_size_align(
+        SIZE.saturating_sub(mem::size_of::<Header>()) & !(ALIGN - 1),
+        ALIGN,
+    ) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid layout of stacks"),
+    };
+
+    /// Creates a pool with all the slots available.
+    pub const fn new() -> Self {
+        StaticStackPool {
+            slots: [const { Slot(UnsafeCell::new([MaybeUninit::uninit(); SIZE])) }; N],
+            used: [const { AtomicBool::new(false) }; N],
+        }
+    }
+
+    /// The number of slots not in use.
+    pub fn available(&self) -> usize {
+        self.used
+            .iter()
+            .filter(|used| !used.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+impl<const N: usize, const SIZE: usize> Default for StaticStackPool<N, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const SIZE: usize> fmt::Debug for StaticStackPool<N, SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticStackPool")
+            .field("slots", &N)
+            .field("size", &SIZE)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+// SAFETY: Each slot is acquired exclusively, and released only by the dropper
+// with the flag saved on top of the usable memory.
+unsafe impl<const N: usize, const SIZE: usize> StackAllocator
+    for &'static StaticStackPool<N, SIZE>
+{
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        let returned = StaticStackPool::<N, SIZE>::RETURNED;
+        if layout.size() > returned.size() || layout.align() > returned.align() {
+            return Err(AllocError);
+        }
+        let index = self
+            .used
+            .iter()
+            .position(|used| {
+                let res = used.compare_exchange(
+                    false,
+                    true,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                );
+                res.is_ok()
+            })
+            .ok_or(AllocError)?;
+
+        let memory = NonNull::new(self.slots[index].0.get().cast::<u8>()).unwrap();
+        let slot = Layout::from_size_align(SIZE, ALIGN).unwrap();
+        // SAFETY: The slot is acquired above, and owned by the stack.
+        let inner = unsafe { Stack::new(memory, slot, forget) };
+        // SAFETY: The slot is aligned to `ALIGN`, and released by `release`.
+        let stack =
+            unsafe { with_header(inner, returned, |_| &self.used[index], release) };
+        stack.inspect_err(|_| self.used[index].store(false, Ordering::Release))
+    }
+}
+
+impl<const N: usize, const SIZE: usize> From<&'static StaticStackPool<N, SIZE>>
+    for Stack
+{
+    fn from(pool: &'static StaticStackPool<N, SIZE>) -> Self {
+        Self::from((&pool, StaticStackPool::<N, SIZE>::RETURNED))
+    }
+}
+
+// SAFETY: The stacks are owned, and valid until dropped.
+unsafe impl<const N: usize, const SIZE: usize> IntoStack<'_>
+    for &'static StaticStackPool<N, SIZE>
+{
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        let layout = StaticStackPool::<N, SIZE>::RETURNED;
+        (&self).allocate(layout).map_err(|_| layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{alloc::Layout, mem::MaybeUninit};
+
+    use super::{BorrowedStack, StaticStackPool};
+    use crate::{Stack, StackAllocator};
+
+    #[test]
+    fn borrowed() {
+        let mut memory = [MaybeUninit::uninit(); 4099];
+        let start = memory.as_ptr().addr();
+        let stack = BorrowedStack::from(&mut memory[1..]);
+        let base = stack.stack().base().addr().get();
+        assert!(base.is_multiple_of(16));
+        assert!(base > start && base <= start + 16);
+        assert!(stack.stack().layout().size() > 4096 - 32);
+        drop(stack);
+
+        let mut memory = [MaybeUninit::uninit(); 8];
+        let stack = BorrowedStack::new(&mut memory);
+        assert!(stack.stack().layout().size() < 16);
+    }
+
+    #[test]
+    fn from_static() {
+        static mut MEMORY: [MaybeUninit<u8>; 4096] = [MaybeUninit::uninit(); 4096];
+        let memory = &raw mut MEMORY;
+        // SAFETY: The memory is only used here.
+        let stack = Stack::from_static(unsafe { &mut *memory });
+        assert!(stack.layout().size() >= 4096 - 16);
+    }
+
+    #[test]
+    fn pool() {
+        static POOL: StaticStackPool<2, 4096> = StaticStackPool::new();
+        let layout = Layout::from_size_align(2048, 16).unwrap();
+
+        let a = (&POOL).allocate(layout).unwrap();
+        let b = (&POOL).allocate(layout).unwrap();
+        assert!(a.layout().size() >= 2048 && a.layout().size() < 4096);
+        assert!((&POOL).allocate(layout).is_err());
+        assert_eq!(POOL.available(), 0);
+
+        let base = a.base();
+        drop(a);
+        let c = (&POOL).allocate(layout).unwrap();
+        assert_eq!(c.base(), base);
+
+        // Layouts larger than a slot are rejected.
+        drop((b, c));
+        let large = Layout::from_size_align(8192, 16).unwrap();
+        assert!((&POOL).allocate(large).is_err());
+        assert_eq!(POOL.available(), 2);
+    }
+}
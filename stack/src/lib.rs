@@ -8,21 +8,149 @@
 #![feature(allocator_api)]
 #![feature(allow_internal_unstable)]
 #![feature(slice_ptr_get)]
+#![cfg_attr(feature = "default-stack-allocator", feature(linkage))]
 //! This module tackles with stacks.
 //!
 //! We have [a stack structure](Stack) that keep track of its own memory, and
 //! a trait represents [a stack allocator](StackAllocator).
 
+#[cfg(feature = "default-stack-allocator")]
+extern crate alloc;
+
+#[cfg(any(
+    feature = "limit",
+    feature = "overflow",
+    feature = "paint",
+    feature = "pool"
+))]
+extern crate std;
+
+mod borrowed;
+#[cfg(feature = "limit")]
+mod limit;
+#[cfg(feature = "mmap")]
+mod mmap;
+#[cfg(feature = "overflow")]
+pub mod overflow;
+#[cfg(feature = "paint")]
+mod paint;
+#[cfg(feature = "pool")]
+mod pool;
+
 use core::{
     alloc::{AllocError, Allocator, Layout},
     mem::{self, MaybeUninit},
     ptr::NonNull,
+    slice,
 };
 
+pub use self::borrowed::{BorrowedStack, StaticStackPool};
+#[cfg(feature = "limit")]
+pub use self::limit::{Limited, StackUsage};
+#[cfg(feature = "mmap")]
+pub use self::mmap::{LazyStackAllocator, MmapStackAllocator};
+#[cfg(feature = "paint")]
+pub use self::paint::{Histogram, Painted};
+#[cfg(feature = "pool")]
+pub use self::pool::{PoolStats, StackPool};
+
 // SAFETY: The alignment is a power of 2.
 pub const DEFAULT_LAYOUT: Layout =
     unsafe { Layout::from_size_align_unchecked(4096 * 6, 4096) };
 
+/// The byte that painted stacks are filled with before use.
+///
+/// See [`Stack::with_paint`] for more information.
+pub const PAINT: u8 = 0xa5;
+
+/// Measures the used size of painted memory `[base, base + size)`, which grows
+/// downwards.
+///
+/// # Safety
+///
+/// The memory must be valid for reads, and not written concurrently.
+unsafe fn high_water_mark(base: NonNull<u8>, size: usize) -> usize {
+    // SAFETY: The memory is valid for reads.
+    let memory = unsafe { slice::from_raw_parts(base.as_ptr(), size) };
+    memory
+        .iter()
+        .position(|&b| b != PAINT)
+        .map_or(0, |pos| size - pos)
+}
+
+/// The dropper of stacks on memory not owned by them.
+unsafe fn forget(_: NonNull<u8>, _: Layout) {}
+
+/// The header of type `H` saved on top of the usable memory `returned` of a
+/// stack, as written by [`with_header`].
+fn header_in<H>(memory: NonNull<u8>, returned: Layout) -> *mut H {
+    memory
+        .as_ptr()
+        .map_addr(|addr| addr + returned.size())
+        .cast::<H>()
+}
+
+/// The layout to allocate from the underlying allocator for a stack of
+/// `layout` with a header of type `H`.
+#[cfg(any(feature = "limit", feature = "paint", feature = "pool"))]
+fn inner_layout<H>(layout: Layout) -> Result<Layout, AllocError> {
+    let align = layout.align().max(mem::align_of::<H>());
+    let size = layout.size() + mem::size_of::<H>() + align;
+    Layout::from_size_align(size, align).map_err(|_| AllocError)
+}
+
+/// Creates a stack of `layout` on the memory of `inner`, with the header made
+/// from `inner` saved on top of the usable memory.
+///
+/// The stack shares the guard region with `inner`. If the memory is too small
+/// for the header, `inner` is dropped and the allocation fails.
+///
+/// # Safety
+///
+/// The memory of `inner` must be aligned to `H` and `layout`. `drop` must read
+/// the header with [`header_in`], and release the memory as required by
+/// [`Stack::new`].
+unsafe fn with_header<H>(
+    inner: Stack,
+    layout: Layout,
+    header: impl FnOnce(Stack) -> H,
+    drop: unsafe fn(NonNull<u8>, Layout),
+) -> Result<Stack, AllocError> {
+    let memory = inner.base();
+    let guard = inner.guard_size();
+    let align = layout.align().max(mem::align_of::<H>());
+    let returned = inner.layout().size().checked_sub(mem::size_of::<H>());
+    let returned = returned.ok_or(AllocError)? & !(align - 1);
+    let returned = Layout::from_size_align(returned, layout.align()).unwrap();
+
+    // SAFETY: The header is saved in the rest of the memory, which is
+    // well-aligned.
+    unsafe { header_in::<H>(memory, returned).write(header(inner)) };
+    // SAFETY: The memory is owned by the header, and released by `drop`.
+    Ok(unsafe { Stack::new(memory, returned, drop).with_guard(guard) })
+}
+
+/// The size of memory pages, or 0 if not queried yet.
+#[cfg(all(unix, any(feature = "mmap", feature = "pool")))]
+static PAGE_SIZE: core::sync::atomic::AtomicUsize =
+    core::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(all(unix, any(feature = "mmap", feature = "pool")))]
+fn page_size() -> usize {
+    use core::sync::atomic::Ordering::Relaxed;
+
+    match PAGE_SIZE.load(Relaxed) {
+        0 => {
+            // SAFETY: The function has no safety requirements.
+            let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+            let size = usize::try_from(size).unwrap_or(4096);
+            PAGE_SIZE.store(size, Relaxed);
+            size
+        }
+        size => size,
+    }
+}
+
 /// The raw stack structure.
 ///
 /// The structure owns its memory, and drops the memory if it's dropped. The
@@ -30,6 +158,8 @@ pub const DEFAULT_LAYOUT: Layout =
 pub struct Stack {
     pointer: NonNull<u8>,
     layout: Layout,
+    guard: usize,
+    painted: bool,
     drop: unsafe fn(NonNull<u8>, Layout),
 }
 
@@ -48,10 +178,55 @@ impl Stack {
         Stack {
             pointer,
             layout,
+            guard: 0,
+            painted: false,
             drop,
         }
     }
 
+    /// Records the size of the guard region right below the stack.
+    ///
+    /// # Safety
+    ///
+    /// The `guard` bytes below [`Stack::base`] must be inaccessible until the
+    /// stack is dropped, so that an overflow of the stack faults in that range.
+    pub unsafe fn with_guard(mut self, guard: usize) -> Self {
+        self.guard = guard;
+        self
+    }
+
+    /// Creates a stack on some static memory, which is never freed.
+    ///
+    /// The memory is aligned to 16 bytes, so that a few bytes at both ends may
+    /// be left unused. See [`BorrowedStack`] for memory borrowed for shorter
+    /// lifetimes.
+    pub fn from_static(memory: &'static mut [MaybeUninit<u8>]) -> Self {
+        // SAFETY: The memory is valid forever.
+        unsafe { borrowed::stack_in(NonNull::from(memory)) }
+    }
+
+    /// Creates a stack on the same memory, which is never freed.
+    ///
+    /// # Safety
+    ///
+    /// The returned stack must not be used after `self` is dropped.
+    pub unsafe fn view(&self) -> Self {
+        // SAFETY: The memory is valid until `self` is dropped, by contract.
+        unsafe { Stack::new(self.pointer, self.layout, forget).with_guard(self.guard) }
+    }
+
+    /// Marks the stack as painted, so that its [high-water
+    /// mark](Stack::high_water_mark) can be measured.
+    ///
+    /// # Safety
+    ///
+    /// The whole usable memory of the stack must be filled with [`PAINT`], and
+    /// not used yet.
+    pub unsafe fn with_paint(mut self) -> Self {
+        self.painted = true;
+        self
+    }
+
     /// The base of the allocated stack.
     pub fn base(&self) -> NonNull<u8> {
         self.pointer
@@ -61,6 +236,36 @@ impl Stack {
     pub fn layout(&self) -> Layout {
         self.layout
     }
+
+    /// The size of the guard region right below [`Stack::base`], or 0 if the
+    /// stack is not guarded.
+    pub fn guard_size(&self) -> usize {
+        self.guard
+    }
+
+    /// The largest size of the stack ever used since its allocation, in bytes,
+    /// or `None` if the stack is not [painted](Stack::with_paint).
+    ///
+    /// The used size is measured by the bytes overwritten from the top of the
+    /// stack, so it may be slightly smaller than the actual one if some data
+    /// happened to be written as [`PAINT`].
+    ///
+    /// The stack must not be in use by other threads. Measuring a stack
+    /// touches all of its pages.
+    pub fn high_water_mark(&self) -> Option<usize> {
+        // SAFETY: The memory is valid for `self.layout`, and owned by `self`.
+        self.painted
+            .then(|| unsafe { high_water_mark(self.pointer, self.layout.size()) })
+    }
+
+    /// The size of the usable memory currently backed by physical pages, in
+    /// contrast to the reserved size reported by [`Stack::layout`].
+    ///
+    /// Only whole pages within the stack are counted, with `mincore`.
+    #[cfg(feature = "mmap")]
+    pub fn committed(&self) -> usize {
+        mmap::committed(self.pointer, self.layout.size())
+    }
 }
 
 impl Drop for Stack {
@@ -94,6 +299,9 @@ impl<A: StackAllocator> From<&A> for Stack {
     }
 }
 
+/// # Panics
+///
+/// Panics on the errors of [`StackAllocator::allocate`].
 impl<A: StackAllocator> From<(&A, Layout)> for Stack {
     fn from((alloc, layout): (&A, Layout)) -> Self {
         match alloc.allocate(layout) {
@@ -103,6 +311,90 @@ impl<A: StackAllocator> From<(&A, Layout)> for Stack {
     }
 }
 
+/// Sources of [`Stack`]s that are only valid for `'a`, used by coroutine
+/// builders.
+///
+/// Unlike the conversions with [`From`], which panic if the allocation fails,
+/// the layout failed to allocate is returned as the error, so that builders can
+/// report it.
+///
+/// Owned stacks and allocators implement this trait for any lifetime, while
+/// [`BorrowedStack`] implements it for the lifetime of its memory. Coroutines
+/// created on such stacks must not outlive `'a`.
+///
+/// # Safety
+///
+/// The returned stack must be valid for `'a`.
+pub unsafe trait IntoStack<'a> {
+    /// Converts `self` into a stack, or returns the layout failed to allocate.
+    ///
+    /// # Safety
+    ///
+    /// The returned stack must not be used after `'a`.
+    unsafe fn into_stack(self) -> Result<Stack, Layout>;
+
+    /// The layout to be allocated by [`IntoStack::into_stack`], if any, so that
+    /// builders can reject it before the allocation.
+    fn layout(&self) -> Option<Layout> {
+        None
+    }
+}
+
+// SAFETY: Owned stacks are valid until dropped.
+unsafe impl IntoStack<'_> for Stack {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        Ok(self)
+    }
+}
+
+// SAFETY: Same as above.
+unsafe impl IntoStack<'_> for () {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        // SAFETY: The contract is the same.
+        unsafe { DEFAULT_LAYOUT.into_stack() }
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        Some(DEFAULT_LAYOUT)
+    }
+}
+
+// SAFETY: Same as above.
+unsafe impl IntoStack<'_> for Layout {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        // SAFETY: The contract is the same.
+        unsafe { (&Global, self).into_stack() }
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        Some(*self)
+    }
+}
+
+// SAFETY: Same as above.
+unsafe impl<A: StackAllocator> IntoStack<'_> for &A {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        // SAFETY: The contract is the same.
+        unsafe { (self, DEFAULT_LAYOUT).into_stack() }
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        Some(DEFAULT_LAYOUT)
+    }
+}
+
+// SAFETY: Same as above.
+unsafe impl<A: StackAllocator> IntoStack<'_> for (&A, Layout) {
+    unsafe fn into_stack(self) -> Result<Stack, Layout> {
+        let (alloc, layout) = self;
+        alloc.allocate(layout).map_err(|_| layout)
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        Some(self.1)
+    }
+}
+
 /// Generic stack allocators.
 ///
 /// # Safety
@@ -231,12 +523,19 @@ unsafe impl StackAllocator for Global {
 /// This macro works just like `#[global_allocator]` attribute, except it only
 /// receives the path of the target static variable, while the actual definition
 /// can lie elsewhere.
+///
+/// If the `default-stack-allocator` feature is enabled, the global heap
+/// allocator is used as a fallback when this macro is not called anywhere.
 #[allow_internal_unstable(allocator_api)]
 #[macro_export]
 macro_rules! global_stack_allocator {
     ($name:path) => {
+        $crate::global_stack_allocator!(@attrs [] $name);
+    };
+    (@attrs [$($attr:meta),*] $name:path) => {
         #[unsafe(no_mangle)]
         #[doc(hidden)]
+        $(#[$attr])*
         unsafe fn __rust_unico_allocate_stack(
             layout: core::alloc::Layout,
         ) -> Result<$crate::Stack, core::alloc::AllocError> {
@@ -244,3 +543,10 @@ macro_rules! global_stack_allocator {
         }
     };
 }
+
+#[cfg(feature = "default-stack-allocator")]
+mod default_stack_allocator {
+    // The weak definition is overridden by the user's `global_stack_allocator!`,
+    // if any.
+    global_stack_allocator!(@attrs [linkage = "weak"] alloc::alloc::Global);
+}
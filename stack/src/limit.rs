@@ -0,0 +1,230 @@
+//! Stacks bounded in number and total size.
+
+use core::{
+    alloc::{AllocError, Layout},
+    fmt,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+use std::sync::{Arc, OnceLock};
+
+use crate::{Stack, StackAllocator, header_in, inner_layout, with_header};
+
+/// The stacks currently lent by a [`Limited`] allocator.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct StackUsage {
+    /// The number of live stacks.
+    pub stacks: usize,
+    /// The total size of live stacks requested from the underlying allocator.
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_stacks: usize,
+    max_bytes: usize,
+}
+
+/// The accounting of a [`Limited`] allocator shared with all its stacks.
+struct Shared {
+    limits: Limits,
+    stacks: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl Shared {
+    /// Accounts a new stack of `bytes`, or returns `false` if any limit would
+    /// be exceeded.
+    fn acquire(&self, bytes: usize) -> bool {
+        let stacks = self.stacks.fetch_add(1, Relaxed);
+        if stacks >= self.limits.max_stacks {
+            self.stacks.fetch_sub(1, Relaxed);
+            return false;
+        }
+        let total = self.bytes.fetch_add(bytes, Relaxed);
+        if total.saturating_add(bytes) > self.limits.max_bytes {
+            self.release(bytes);
+            return false;
+        }
+        true
+    }
+
+    fn release(&self, bytes: usize) {
+        self.stacks.fetch_sub(1, Relaxed);
+        self.bytes.fetch_sub(bytes, Relaxed);
+    }
+}
+
+/// The record of a stack lent by a [`Limited`] allocator, saved on top of its
+/// usable memory.
+struct Header {
+    inner: Stack,
+    shared: Arc<Shared>,
+    bytes: usize,
+}
+
+unsafe fn release(memory: NonNull<u8>, returned: Layout) {
+    // SAFETY: The header is written in `allocate`, and never modified.
+    let Header {
+        inner,
+        shared,
+        bytes,
+    } = unsafe { header_in::<Header>(memory, returned).read() };
+    drop(inner);
+    shared.release(bytes);
+}
+
+/// The stack allocator that bounds the number and the total size of live
+/// stacks allocated by `A`.
+///
+/// The allocation fails with [`AllocError`] if any limit would be exceeded,
+/// which is reported by coroutine builders as a recoverable error:
+///
+/// ```
+/// # #![feature(allocator_api)]
+/// use std::alloc::Global;
+///
+/// use unico_stack::{DEFAULT_LAYOUT, Limited, StackAllocator};
+///
+/// let limited = Limited::new(Global).max_stacks(1);
+/// let stack = limited.allocate(DEFAULT_LAYOUT).unwrap();
+/// assert!(limited.allocate(DEFAULT_LAYOUT).is_err());
+/// assert_eq!(limited.usage().stacks, 1);
+///
+/// drop(stack);
+/// assert!(limited.allocate(DEFAULT_LAYOUT).is_ok());
+/// ```
+///
+/// The size of each stack is accounted as the size requested from `A`, which
+/// is slightly larger than the requested layout.
+pub struct Limited<A> {
+    alloc: A,
+    limits: Limits,
+    shared: OnceLock<Arc<Shared>>,
+}
+
+impl<A> Limited<A> {
+    /// Creates an allocator over `alloc`, without any limit.
+    pub const fn new(alloc: A) -> Self {
+        Limited {
+            alloc,
+            limits: Limits {
+                max_stacks: usize::MAX,
+                max_bytes: usize::MAX,
+            },
+            shared: OnceLock::new(),
+        }
+    }
+
+    /// Sets the maximum number of live stacks.
+    pub const fn max_stacks(mut self, stacks: usize) -> Self {
+        self.limits.max_stacks = stacks;
+        self
+    }
+
+    /// Sets the maximum total size of live stacks.
+    pub const fn max_bytes(mut self, bytes: usize) -> Self {
+        self.limits.max_bytes = bytes;
+        self
+    }
+
+    fn shared(&self) -> &Arc<Shared> {
+        self.shared.get_or_init(|| {
+            Arc::new(Shared {
+                limits: self.limits,
+                stacks: AtomicUsize::new(0),
+                bytes: AtomicUsize::new(0),
+            })
+        })
+    }
+
+    /// The stacks currently alive.
+    pub fn usage(&self) -> StackUsage {
+        match self.shared.get() {
+            Some(shared) => StackUsage {
+                stacks: shared.stacks.load(Relaxed),
+                bytes: shared.bytes.load(Relaxed),
+            },
+            None => StackUsage::default(),
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for Limited<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Limited")
+            .field("alloc", &self.alloc)
+            .field("limits", &self.limits)
+            .field("usage", &self.usage())
+            .finish()
+    }
+}
+
+// SAFETY: The stacks are allocated by `A`, and only freed by the dropper, with
+// the original stacks saved on top of the usable memory.
+unsafe impl<A: StackAllocator> StackAllocator for Limited<A> {
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        let shared = self.shared();
+        let inner = inner_layout::<Header>(layout)?;
+        let size = inner.size();
+        if !shared.acquire(size) {
+            return Err(AllocError);
+        }
+        let inner = match self.alloc.allocate(inner) {
+            Ok(inner) => inner,
+            Err(err) => {
+                shared.release(size);
+                return Err(err);
+            }
+        };
+
+        let header = |inner| Header {
+            inner,
+            shared: shared.clone(),
+            bytes: size,
+        };
+        // SAFETY: The inner stack is aligned for the header, and freed by
+        // `release`.
+        let stack = unsafe { with_header(inner, layout, header, release) };
+        stack.inspect_err(|_| shared.release(size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::Global, vec::Vec};
+
+    use super::{Limited, StackUsage};
+    use crate::{DEFAULT_LAYOUT, StackAllocator};
+
+    #[test]
+    fn max_stacks() {
+        let limited = Limited::new(Global).max_stacks(3);
+        let stacks = (0..3)
+            .map(|_| limited.allocate(DEFAULT_LAYOUT).unwrap())
+            .collect::<Vec<_>>();
+        assert!(limited.allocate(DEFAULT_LAYOUT).is_err());
+        let usage = limited.usage();
+        assert_eq!(usage.stacks, 3);
+        assert!(usage.bytes > DEFAULT_LAYOUT.size() * 3);
+
+        drop(stacks);
+        assert_eq!(limited.usage(), StackUsage::default());
+    }
+
+    #[test]
+    fn max_bytes() {
+        let limited = Limited::new(Global).max_bytes(DEFAULT_LAYOUT.size() * 5 / 2);
+        let a = limited.allocate(DEFAULT_LAYOUT).unwrap();
+        let b = limited.allocate(DEFAULT_LAYOUT).unwrap();
+        // The usage is unchanged by the failure.
+        let usage = limited.usage();
+        assert!(limited.allocate(DEFAULT_LAYOUT).is_err());
+        assert_eq!(limited.usage(), usage);
+
+        drop(a);
+        let c = limited.allocate(DEFAULT_LAYOUT).unwrap();
+        assert_eq!(limited.usage().stacks, 2);
+        drop((b, c));
+    }
+}
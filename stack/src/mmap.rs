@@ -0,0 +1,304 @@
+//! Stacks mapped directly from the OS, guarded against overflows.
+
+use core::{
+    alloc::{AllocError, Layout},
+    mem,
+    ptr::{self, NonNull},
+};
+
+use crate::{Stack, StackAllocator, page_size};
+
+/// The mapping of a stack, saved on top of its usable memory.
+#[derive(Clone, Copy)]
+struct Mapping {
+    base: NonNull<u8>,
+    len: usize,
+}
+
+fn mapping_in(memory: NonNull<u8>, returned: Layout) -> *mut Mapping {
+    memory
+        .as_ptr()
+        .map_addr(|addr| addr + returned.size())
+        .cast::<Mapping>()
+}
+
+unsafe fn unmap(memory: NonNull<u8>, returned: Layout) {
+    // SAFETY: The mapping is written in `allocate`, and never modified.
+    let Mapping { base, len } = unsafe { mapping_in(memory, returned).read() };
+    // SAFETY: The memory is mapped in `allocate`.
+    let ret = unsafe { libc::munmap(base.as_ptr().cast(), len) };
+    debug_assert_eq!(ret, 0, "failed to unmap a stack");
+}
+
+/// The stack allocator that maps every stack from the OS with [`mmap`], and
+/// protects the pages right below the stack, so that an overflow faults
+/// instead of overwriting other memory silently.
+///
+/// Every mapping is rounded up to whole pages, so the usable size reported by
+/// [`Stack::layout`] may be larger than requested. Alignments larger than a
+/// page are not supported.
+///
+/// [`mmap`]: https://man7.org/linux/man-pages/man2/mmap.2.html
+#[derive(Debug, Clone, Copy)]
+pub struct MmapStackAllocator {
+    guard_pages: usize,
+}
+
+impl MmapStackAllocator {
+    /// Creates a stack allocator with 1 guard page below each stack.
+    pub const fn new() -> Self {
+        Self::with_guard_pages(1)
+    }
+
+    /// Creates a stack allocator with `guard_pages` guard pages below each
+    /// stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard_pages` is 0.
+    pub const fn with_guard_pages(guard_pages: usize) -> Self {
+        assert!(guard_pages > 0, "at least 1 guard page is required");
+        MmapStackAllocator { guard_pages }
+    }
+
+    /// The number of guard pages below each stack.
+    pub const fn guard_pages(&self) -> usize {
+        self.guard_pages
+    }
+}
+
+impl Default for MmapStackAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps a stack of `layout` with `guard_pages` guard pages below it, and
+/// `flags` added to the mapping.
+fn map(
+    layout: Layout,
+    guard_pages: usize,
+    flags: libc::c_int,
+) -> Result<Stack, AllocError> {
+    let page = page_size();
+    if layout.align() > page {
+        return Err(AllocError);
+    }
+    let size = layout
+        .size()
+        .checked_add(mem::size_of::<Mapping>() + layout.align())
+        .and_then(|size| size.checked_next_multiple_of(page))
+        .ok_or(AllocError)?;
+    let guard = guard_pages.checked_mul(page).ok_or(AllocError)?;
+    let len = size.checked_add(guard).ok_or(AllocError)?;
+
+    // OpenBSD refuses to switch to stacks not mapped with `MAP_STACK`.
+    #[cfg(target_os = "openbsd")]
+    const FLAGS: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON | libc::MAP_STACK;
+    #[cfg(not(target_os = "openbsd"))]
+    const FLAGS: libc::c_int = libc::MAP_PRIVATE | libc::MAP_ANON;
+
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    // SAFETY: A fresh anonymous mapping is requested.
+    let base = unsafe { libc::mmap(ptr::null_mut(), len, prot, FLAGS | flags, -1, 0) };
+    if base == libc::MAP_FAILED {
+        return Err(AllocError);
+    }
+    // SAFETY: The guard pages are the bottom of the mapping above.
+    if unsafe { libc::mprotect(base, guard, libc::PROT_NONE) } != 0 {
+        // SAFETY: The mapping is not used anywhere.
+        unsafe { libc::munmap(base, len) };
+        return Err(AllocError);
+    }
+    let base = NonNull::new(base.cast::<u8>()).ok_or(AllocError)?;
+
+    // SAFETY: The guard pages are within the mapping.
+    let memory = unsafe { base.add(guard) };
+    let align = layout.align().max(mem::align_of::<Mapping>());
+    let returned = (size - mem::size_of::<Mapping>()) & !(align - 1);
+    let returned = Layout::from_size_align(returned, layout.align()).unwrap();
+
+    // SAFETY: The mapping is saved in the rest of the memory, which is
+    // well-aligned.
+    unsafe { mapping_in(memory, returned).write(Mapping { base, len }) };
+    // SAFETY: The memory is unmapped by `unmap` with the mapping saved, and
+    // the guard pages are right below it.
+    Ok(unsafe { Stack::new(memory, returned, unmap).with_guard(guard) })
+}
+
+// SAFETY: The stack is mapped exclusively, and unmapped with the mapping saved
+// on top of the usable memory.
+unsafe impl StackAllocator for MmapStackAllocator {
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        map(layout, self.guard_pages, 0)
+    }
+}
+
+/// The stack allocator that reserves a large range of memory for every stack,
+/// of which only the top pages are committed up front.
+///
+/// The rest of the stack is faulted in by the OS on demand, so a large number
+/// of stacks that are usually shallow take little physical memory, while any
+/// of them may still recurse deeply. [`Stack::layout`] reports the whole
+/// reservation, and [`Stack::committed`] reports the resident part.
+///
+/// Stacks are guarded in the same way as [`MmapStackAllocator`]. On Linux, the
+/// reservation is mapped with `MAP_NORESERVE` so that it's not charged against
+/// the overcommit limit.
+#[derive(Debug, Clone, Copy)]
+pub struct LazyStackAllocator {
+    reserve: usize,
+    commit: usize,
+    guard_pages: usize,
+}
+
+impl LazyStackAllocator {
+    /// Creates a stack allocator that reserves 1 MiB and commits 8 KiB of
+    /// each stack, with 1 guard page below it.
+    pub const fn new() -> Self {
+        LazyStackAllocator {
+            reserve: 1 << 20,
+            commit: 8 << 10,
+            guard_pages: 1,
+        }
+    }
+
+    /// Sets the minimum size reserved for each stack.
+    ///
+    /// Layouts requesting a larger size are reserved as requested.
+    pub const fn reserve(mut self, bytes: usize) -> Self {
+        self.reserve = bytes;
+        self
+    }
+
+    /// Sets the size committed up front at the top of each stack.
+    pub const fn commit(mut self, bytes: usize) -> Self {
+        self.commit = bytes;
+        self
+    }
+
+    /// Sets the number of guard pages below each stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `guard_pages` is 0.
+    pub const fn guard_pages(mut self, guard_pages: usize) -> Self {
+        assert!(guard_pages > 0, "at least 1 guard page is required");
+        self.guard_pages = guard_pages;
+        self
+    }
+}
+
+impl Default for LazyStackAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: See the implementation of `MmapStackAllocator`.
+unsafe impl StackAllocator for LazyStackAllocator {
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        const FLAGS: libc::c_int = libc::MAP_NORESERVE;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        const FLAGS: libc::c_int = 0;
+
+        let size = layout.size().max(self.reserve);
+        let reserved =
+            Layout::from_size_align(size, layout.align()).map_err(|_| AllocError)?;
+        let stack = map(reserved, self.guard_pages, FLAGS)?;
+
+        // Touch the top pages so that they're faulted in now.
+        let page = page_size();
+        let top = stack.base().addr().get() + stack.layout().size();
+        let bottom = top
+            .saturating_sub(self.commit)
+            .max(stack.base().addr().get());
+        let mut addr = top & !(page - 1);
+        while addr > bottom {
+            addr -= page;
+            let ptr = stack.base().as_ptr().with_addr(addr.max(bottom));
+            // SAFETY: The pointer is within the stack, which is not used yet.
+            unsafe { ptr.write_volatile(0) };
+        }
+        Ok(stack)
+    }
+}
+
+/// Counts the resident bytes of whole pages within `[start, start + len)`.
+pub(crate) fn committed(start: NonNull<u8>, len: usize) -> usize {
+    let page = page_size();
+    let start = start.addr().get();
+    let (first, end) = (start.next_multiple_of(page), (start + len) & !(page - 1));
+    let mut resident = 0;
+    let mut addr = first;
+    while addr < end {
+        let mut vec = [0u8; 64];
+        let chunk = ((end - addr) / page).min(vec.len());
+        // SAFETY: The range is mapped within the stack, and `vec` is large
+        // enough for the pages in the chunk.
+        let ret = unsafe {
+            libc::mincore(
+                ptr::without_provenance_mut(addr),
+                chunk * page,
+                vec.as_mut_ptr().cast(),
+            )
+        };
+        if ret != 0 {
+            break;
+        }
+        resident += vec[..chunk].iter().filter(|&&v| v & 1 != 0).count() * page;
+        addr += chunk * page;
+    }
+    resident
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+
+    use super::{LazyStackAllocator, MmapStackAllocator, page_size};
+    use crate::{DEFAULT_LAYOUT, StackAllocator};
+
+    #[test]
+    fn allocate() {
+        let alloc = MmapStackAllocator::with_guard_pages(2);
+        let stack = alloc.allocate(DEFAULT_LAYOUT).unwrap();
+        assert!(stack.layout().size() >= DEFAULT_LAYOUT.size());
+        assert!(stack.base().addr().get().is_multiple_of(page_size()));
+
+        // The whole stack is usable.
+        let base = stack.base().as_ptr();
+        // SAFETY: The memory is valid for `stack.layout()`.
+        unsafe { base.write_bytes(0xcc, stack.layout().size()) };
+        drop(stack);
+    }
+
+    #[test]
+    fn unaligned() {
+        let layout = Layout::from_size_align(100, 1).unwrap();
+        let stack = MmapStackAllocator::new().allocate(layout).unwrap();
+        assert!(stack.layout().size() >= layout.size());
+
+        let layout = Layout::from_size_align(4096, page_size() * 2).unwrap();
+        assert!(MmapStackAllocator::new().allocate(layout).is_err());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn lazy() {
+        let alloc = LazyStackAllocator::new().commit(page_size() * 2);
+        let stack = alloc.allocate(DEFAULT_LAYOUT).unwrap();
+        assert!(stack.layout().size() >= 1 << 20);
+        let committed = stack.committed();
+        assert!(committed >= page_size(), "{committed}");
+        assert!(committed <= page_size() * 3, "{committed}");
+
+        // Touching more pages commits them.
+        let top = stack.base().as_ptr().wrapping_add(stack.layout().size());
+        let deep = top.wrapping_sub(page_size() * 64);
+        // SAFETY: The memory is within the stack.
+        unsafe { deep.write_bytes(0xcc, page_size() * 16) };
+        assert!(stack.committed() >= committed + page_size() * 16);
+    }
+}
@@ -0,0 +1,234 @@
+//! Diagnostics of stack overflows in coroutines.
+//!
+//! Once [`install`]ed, a handler of `SIGSEGV` and `SIGBUS` checks whether the
+//! faulting address lies in the guard region of the stack running on the
+//! current thread, and if so, reports the overflowing coroutine before aborting
+//! the process, just like what `std` does for threads:
+//!
+//! ```text
+//! stack overflow in coroutine `main::{{closure}}` at 0x7f0e8c3f6000 (stack size 24576)
+//! fatal runtime error: stack overflow, aborting
+//! ```
+//!
+//! Only a stack can overflow while it's running, so instead of looking up all
+//! the live stacks, the running one is [registered](set_current) whenever the
+//! control flow switches to it. Other faults are forwarded to the previous
+//! handlers, e.g. the one of `std` detecting overflows of thread stacks.
+//!
+//! The handler runs on the alternate signal stack of the faulting thread.
+//! `std` sets one up for the threads it spawns, and [`install`] sets up one for
+//! the current thread if absent. Other threads running coroutines must call
+//! [`install`] as well.
+
+use core::{
+    cell::Cell,
+    ffi::c_void,
+    fmt::{self, Write},
+    mem, ptr,
+};
+use std::sync::OnceLock;
+
+use crate::Stack;
+
+/// The guard region of a stack, with the name of its coroutine.
+#[derive(Debug, Clone, Copy)]
+pub struct GuardRegion {
+    start: usize,
+    base: usize,
+    size: usize,
+    name: &'static str,
+}
+
+impl GuardRegion {
+    /// Creates the region of `stack` running the coroutine named `name`, or
+    /// `None` if the stack is not guarded.
+    pub fn new(stack: &Stack, name: &'static str) -> Option<Self> {
+        let base = stack.base().addr().get();
+        (stack.guard_size() > 0).then(|| GuardRegion {
+            start: base - stack.guard_size(),
+            base,
+            size: stack.layout().size(),
+            name,
+        })
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.base).contains(&addr)
+    }
+}
+
+std::thread_local! {
+    static CURRENT: Cell<Option<GuardRegion>> = const { Cell::new(None) };
+}
+
+/// Sets the guard region of the stack running on the current thread, and
+/// returns the previous one.
+///
+/// `None` stands for the stack of the thread itself.
+pub fn set_current(region: Option<GuardRegion>) -> Option<GuardRegion> {
+    CURRENT.replace(region)
+}
+
+/// The previous actions of `SIGSEGV` and `SIGBUS`.
+static PREVIOUS: OnceLock<[libc::sigaction; 2]> = OnceLock::new();
+
+const SIGNALS: [libc::c_int; 2] = [libc::SIGSEGV, libc::SIGBUS];
+
+/// The size of alternate signal stacks set up by [`install`].
+const ALTSTACK_SIZE: usize = 64 << 10;
+
+/// Installs the handler of stack overflows, and sets up an alternate signal
+/// stack for the current thread if absent.
+///
+/// The handler is only installed once, while alternate stacks are set up per
+/// thread and never freed.
+pub fn install() {
+    PREVIOUS.get_or_init(|| {
+        // SAFETY: The structures are plain old data.
+        let mut action: libc::sigaction = unsafe { mem::zeroed() };
+        action.sa_sigaction = handler as *const () as usize;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+        // SAFETY: Same as above.
+        let mut previous: [libc::sigaction; 2] = unsafe { mem::zeroed() };
+        for (signal, previous) in SIGNALS.into_iter().zip(&mut previous) {
+            // SAFETY: The action is valid.
+            unsafe {
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(signal, &action, previous);
+            }
+        }
+        previous
+    });
+
+    // SAFETY: Same as above.
+    let mut old: libc::stack_t = unsafe { mem::zeroed() };
+    // SAFETY: The alternate stack is only queried.
+    unsafe { libc::sigaltstack(ptr::null(), &mut old) };
+    if old.ss_flags & libc::SS_DISABLE == 0 {
+        return;
+    }
+    let prot = libc::PROT_READ | libc::PROT_WRITE;
+    let flags = libc::MAP_PRIVATE | libc::MAP_ANON;
+    // SAFETY: A fresh anonymous mapping is requested.
+    let sp = unsafe { libc::mmap(ptr::null_mut(), ALTSTACK_SIZE, prot, flags, -1, 0) };
+    if sp == libc::MAP_FAILED {
+        return;
+    }
+    let new = libc::stack_t {
+        ss_sp: sp,
+        ss_flags: 0,
+        ss_size: ALTSTACK_SIZE,
+    };
+    // SAFETY: The alternate stack is valid forever.
+    unsafe { libc::sigaltstack(&new, ptr::null_mut()) };
+}
+
+/// A buffer formatting messages without allocation.
+struct Message {
+    buf: [u8; 512],
+    len: usize,
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..][..len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+fn report(region: &GuardRegion) {
+    let mut msg = Message {
+        buf: [0; 512],
+        len: 0,
+    };
+    let _ = write!(
+        msg,
+        "\nstack overflow in coroutine `{}` at {:#x} (stack size {})\n\
+        fatal runtime error: stack overflow, aborting\n",
+        region.name, region.base, region.size,
+    );
+    // SAFETY: The buffer is valid.
+    unsafe { libc::write(libc::STDERR_FILENO, msg.buf.as_ptr().cast(), msg.len) };
+}
+
+unsafe extern "C" fn handler(
+    signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    context: *mut c_void,
+) {
+    // SAFETY: `info` is valid for `SA_SIGINFO` handlers.
+    let addr = unsafe { (*info).si_addr() }.addr();
+    let current = CURRENT.try_with(Cell::get).ok().flatten();
+    if let Some(region) = current.filter(|region| region.contains(addr)) {
+        report(&region);
+        // SAFETY: The function has no safety requirements.
+        unsafe { libc::abort() };
+    }
+
+    let index = SIGNALS.iter().position(|&s| s == signal);
+    let previous = PREVIOUS.get().zip(index).map(|(previous, i)| previous[i]);
+    match previous {
+        Some(previous)
+            if previous.sa_sigaction != libc::SIG_DFL
+                && previous.sa_sigaction != libc::SIG_IGN =>
+        {
+            if previous.sa_flags & libc::SA_SIGINFO != 0 {
+                type Action =
+                    unsafe extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut c_void);
+                // SAFETY: The previous handler is installed with `SA_SIGINFO`.
+                let action =
+                    unsafe { mem::transmute::<usize, Action>(previous.sa_sigaction) };
+                // SAFETY: The arguments are passed as is.
+                unsafe { action(signal, info, context) }
+            } else {
+                type Handler = unsafe extern "C" fn(libc::c_int);
+                // SAFETY: The previous handler is installed without `SA_SIGINFO`.
+                let handler =
+                    unsafe { mem::transmute::<usize, Handler>(previous.sa_sigaction) };
+                // SAFETY: Same as above.
+                unsafe { handler(signal) }
+            }
+        }
+        _ => {
+            // Restore the default action, so that the fault happens again and
+            // terminates the process after returning.
+            // SAFETY: The structure is plain old data.
+            let mut action: libc::sigaction = unsafe { mem::zeroed() };
+            action.sa_sigaction = libc::SIG_DFL;
+            // SAFETY: The action is valid.
+            unsafe { libc::sigaction(signal, &action, ptr::null_mut()) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{alloc::Layout, ptr::NonNull};
+
+    use super::GuardRegion;
+    use crate::Stack;
+
+    #[test]
+    fn region() {
+        let mut memory = [0u8; 8192];
+        let base = NonNull::from(&mut memory).cast::<u8>();
+        let layout = Layout::from_size_align(4096, 16).unwrap();
+
+        // SAFETY: The memory outlives the stacks, which drop nothing.
+        let stack = unsafe { Stack::new(base, layout, |_, _| {}) };
+        assert!(GuardRegion::new(&stack, "plain").is_none());
+
+        // SAFETY: Same as above. The guard region is not really inaccessible,
+        // but it's never touched.
+        let stack =
+            unsafe { Stack::new(base.add(4096), layout, |_, _| {}).with_guard(4096) };
+        let region = GuardRegion::new(&stack, "guarded").unwrap();
+        let base = stack.base().addr().get();
+        assert!(region.contains(base - 1));
+        assert!(region.contains(base - 4096));
+        assert!(!region.contains(base));
+        assert!(!region.contains(base - 4097));
+    }
+}
@@ -0,0 +1,231 @@
+//! Stacks painted before use, so that their usage can be measured.
+
+use core::{
+    alloc::{AllocError, Layout},
+    array, fmt,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicUsize, Ordering::Relaxed},
+};
+use std::sync::{Arc, OnceLock};
+
+use crate::{
+    PAINT, Stack, StackAllocator, header_in, high_water_mark, inner_layout, with_header,
+};
+
+/// The number of buckets in a histogram, one for 0 and one for each power of 2.
+const BUCKETS: usize = usize::BITS as usize + 1;
+
+fn bucket(mark: usize) -> usize {
+    (usize::BITS - mark.leading_zeros()) as usize
+}
+
+/// The distribution of high-water marks of the stacks dropped from a
+/// [`Painted`] allocator.
+///
+/// The high-water marks are grouped by powers of 2, e.g. a stack that used
+/// 3000 bytes is counted in the bucket of `2048..4096`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Histogram {
+    buckets: [usize; BUCKETS],
+    max: usize,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: [0; BUCKETS],
+            max: 0,
+        }
+    }
+}
+
+impl Histogram {
+    /// The number of stacks recorded.
+    pub fn count(&self) -> usize {
+        self.buckets.iter().sum()
+    }
+
+    /// The largest high-water mark recorded, in bytes.
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// The non-empty buckets, as the ranges of high-water marks in bytes and
+    /// the numbers of stacks within them.
+    pub fn buckets(&self) -> impl Iterator<Item = (Range<usize>, usize)> + '_ {
+        let range = |index: usize| match index {
+            0 => 0..1,
+            _ => {
+                let start = 1usize << (index - 1);
+                start..start.saturating_mul(2)
+            }
+        };
+        (self.buckets.iter().enumerate())
+            .filter(|&(_, &count)| count > 0)
+            .map(move |(index, &count)| (range(index), count))
+    }
+}
+
+/// The histogram of a [`Painted`] allocator shared with all its stacks.
+struct Shared {
+    buckets: [AtomicUsize; BUCKETS],
+    max: AtomicUsize,
+}
+
+impl Shared {
+    fn record(&self, mark: usize) {
+        self.buckets[bucket(mark)].fetch_add(1, Relaxed);
+        self.max.fetch_max(mark, Relaxed);
+    }
+}
+
+/// The record of a painted stack, saved on top of its usable memory.
+struct Header {
+    inner: Stack,
+    shared: Arc<Shared>,
+}
+
+unsafe fn record(memory: NonNull<u8>, returned: Layout) {
+    // SAFETY: The header is written in `allocate`, and never modified.
+    let Header { inner, shared } =
+        unsafe { header_in::<Header>(memory, returned).read() };
+    // SAFETY: The stack is no longer used.
+    shared.record(unsafe { high_water_mark(memory, returned.size()) });
+    drop(inner);
+}
+
+/// The stack allocator that paints every stack allocated by `A` with
+/// [`PAINT`], so that its [high-water mark](Stack::high_water_mark) can be
+/// measured.
+///
+/// The high-water marks of dropped stacks are aggregated into a
+/// [`Histogram`], which helps choosing the layouts of stacks from data:
+///
+/// ```
+/// # #![feature(allocator_api)]
+/// use std::alloc::Global;
+///
+/// use unico_stack::{DEFAULT_LAYOUT, Painted, StackAllocator};
+///
+/// let painted = Painted::new(Global);
+/// let stack = painted.allocate(DEFAULT_LAYOUT).unwrap();
+/// assert_eq!(stack.high_water_mark(), Some(0));
+/// drop(stack);
+///
+/// let histogram = painted.histogram();
+/// assert_eq!(histogram.count(), 1);
+/// assert_eq!(histogram.max(), 0);
+/// ```
+///
+/// Painting writes the whole stack, so the memory of lazily committed stacks
+/// is committed all at once.
+pub struct Painted<A> {
+    alloc: A,
+    shared: OnceLock<Arc<Shared>>,
+}
+
+impl<A> Painted<A> {
+    /// Creates a painting allocator over `alloc`.
+    pub const fn new(alloc: A) -> Self {
+        Painted {
+            alloc,
+            shared: OnceLock::new(),
+        }
+    }
+
+    fn shared(&self) -> &Arc<Shared> {
+        self.shared.get_or_init(|| {
+            Arc::new(Shared {
+                buckets: array::from_fn(|_| AtomicUsize::new(0)),
+                max: AtomicUsize::new(0),
+            })
+        })
+    }
+
+    /// The histogram of the high-water marks of the stacks dropped so far.
+    pub fn histogram(&self) -> Histogram {
+        match self.shared.get() {
+            Some(shared) => Histogram {
+                buckets: array::from_fn(|index| shared.buckets[index].load(Relaxed)),
+                max: shared.max.load(Relaxed),
+            },
+            None => Histogram::default(),
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for Painted<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Painted")
+            .field("alloc", &self.alloc)
+            .field("histogram", &self.histogram())
+            .finish()
+    }
+}
+
+// SAFETY: The stacks are allocated by `A`, and only freed by the dropper, with
+// the original stacks saved on top of the usable memory.
+unsafe impl<A: StackAllocator> StackAllocator for Painted<A> {
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        let inner = self.alloc.allocate(inner_layout::<Header>(layout)?)?;
+        let header = |inner| Header {
+            inner,
+            shared: self.shared().clone(),
+        };
+        // SAFETY: The inner stack is aligned for the header, and freed by
+        // `record`.
+        let stack = unsafe { with_header(inner, layout, header, record)? };
+
+        // SAFETY: The memory is valid for the usable layout, and not used yet.
+        unsafe { stack.base().write_bytes(PAINT, stack.layout().size()) };
+        // SAFETY: The stack is painted above.
+        Ok(unsafe { stack.with_paint() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::Global, vec::Vec};
+
+    use super::{Painted, bucket};
+    use crate::{DEFAULT_LAYOUT, StackAllocator};
+
+    #[test]
+    fn buckets() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 1);
+        assert_eq!(bucket(3000), 12);
+        assert_eq!(bucket(usize::MAX), usize::BITS as usize);
+    }
+
+    #[test]
+    fn measure() {
+        let painted = Painted::new(Global);
+        let stack = painted.allocate(DEFAULT_LAYOUT).unwrap();
+        assert_eq!(stack.high_water_mark(), Some(0));
+
+        // Use the top 3000 bytes of the stack.
+        let size = stack.layout().size();
+        let top = stack.base().as_ptr().wrapping_add(size - 3000);
+        // SAFETY: The memory is within the stack.
+        unsafe { top.write_bytes(0, 3000) };
+        assert_eq!(stack.high_water_mark(), Some(3000));
+        drop(stack);
+
+        let stack = painted.allocate(DEFAULT_LAYOUT).unwrap();
+        drop(stack);
+
+        let histogram = painted.histogram();
+        assert_eq!(histogram.count(), 2);
+        assert_eq!(histogram.max(), 3000);
+        let buckets = histogram.buckets().collect::<Vec<_>>();
+        assert_eq!(buckets, [(0..1, 1), (2048..4096, 1)]);
+    }
+
+    #[test]
+    fn unpainted() {
+        let stack = Global.allocate(DEFAULT_LAYOUT).unwrap();
+        assert_eq!(stack.high_water_mark(), None);
+    }
+}
@@ -0,0 +1,474 @@
+//! Stacks recycled instead of freed.
+
+use core::{
+    alloc::{AllocError, Layout},
+    cell::RefCell,
+    fmt, mem, ptr,
+    ptr::NonNull,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed},
+};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, Weak},
+    vec::Vec,
+};
+
+use crate::{Stack, StackAllocator, header_in, inner_layout, with_header};
+
+/// The key of free lists, i.e. the size and the alignment of the requested
+/// layout.
+type Key = (usize, usize);
+
+/// An idle stack allocated by the underlying allocator.
+struct Idle(Stack);
+
+// SAFETY: An idle stack is plain memory not referenced by anyone.
+unsafe impl Send for Idle {}
+
+impl Idle {
+    fn size(&self) -> usize {
+        self.0.layout().size()
+    }
+}
+
+/// The statistics of a [`StackPool`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PoolStats {
+    /// The number of allocations served with recycled stacks.
+    pub hits: usize,
+    /// The number of allocations served by the underlying allocator.
+    pub misses: usize,
+    /// The total size of idle stacks retained in the pool.
+    pub retained: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Config {
+    max_retained: usize,
+    local_capacity: usize,
+    decommit: bool,
+}
+
+/// The state of a pool shared with all its stacks.
+struct Shared {
+    config: Config,
+    /// Whether the pool is dropped, after which no stack is retained.
+    closed: AtomicBool,
+    global: Mutex<BTreeMap<Key, Vec<Idle>>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    retained: AtomicUsize,
+}
+
+/// The idle stacks of some pool cached in the current thread.
+///
+/// The cache doesn't keep the pool alive, and is evicted once the pool is
+/// dropped.
+struct Local {
+    shared: Weak<Shared>,
+    key: Key,
+    stacks: Vec<Idle>,
+}
+
+impl Local {
+    fn of(&self, shared: &Arc<Shared>, key: Key) -> bool {
+        self.key == key && ptr::eq(self.shared.as_ptr(), Arc::as_ptr(shared))
+    }
+
+    fn is_closed(&self) -> bool {
+        self.shared
+            .upgrade()
+            .is_none_or(|shared| shared.closed.load(Relaxed))
+    }
+}
+
+impl Drop for Local {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+        for idle in self.stacks.drain(..) {
+            shared.retained.fetch_sub(idle.size(), Relaxed);
+        }
+    }
+}
+
+std::thread_local! {
+    static LOCAL: RefCell<Vec<Local>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Frees the caches of dropped pools in the current thread.
+fn evict() {
+    let closed = LOCAL.try_with(|local| {
+        let mut local = local.borrow_mut();
+        local
+            .extract_if(.., |local| local.is_closed())
+            .collect::<Vec<_>>()
+    });
+    // The stacks are freed after the cache is released, in case they are
+    // returned to other pools.
+    drop(closed);
+}
+
+impl Shared {
+    fn global(&self) -> MutexGuard<'_, BTreeMap<Key, Vec<Idle>>> {
+        self.global.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn take(self: &Arc<Self>, key: Key) -> Option<Stack> {
+        evict();
+        let local = LOCAL.try_with(|local| {
+            let mut local = local.borrow_mut();
+            let local = local.iter_mut().find(|local| local.of(self, key))?;
+            local.stacks.pop()
+        });
+        let idle = match local {
+            Ok(Some(idle)) => idle,
+            _ => self.global().get_mut(&key)?.pop()?,
+        };
+        self.retained.fetch_sub(idle.size(), Relaxed);
+        self.hits.fetch_add(1, Relaxed);
+        Some(idle.0)
+    }
+
+    fn put(self: &Arc<Self>, key: Key, stack: Stack) {
+        evict();
+        if self.closed.load(Relaxed) {
+            return;
+        }
+        let size = stack.layout().size();
+        let retained = self.retained.fetch_add(size, Relaxed);
+        if retained.saturating_add(size) > self.config.max_retained {
+            self.retained.fetch_sub(size, Relaxed);
+            return;
+        }
+        if self.config.decommit {
+            decommit(&stack);
+        }
+
+        let mut idle = Some(Idle(stack));
+        // The thread-local cache may be destroyed if the current thread is
+        // exiting, in which case the global list is used instead.
+        let _ = LOCAL.try_with(|local| {
+            let capacity = self.config.local_capacity;
+            let mut local = local.borrow_mut();
+            let pos = local.iter().position(|local| local.of(self, key));
+            match pos {
+                Some(pos) if local[pos].stacks.len() < capacity => {
+                    local[pos].stacks.extend(idle.take())
+                }
+                None if capacity > 0 => local.push(Local {
+                    shared: Arc::downgrade(self),
+                    key,
+                    stacks: idle.take().into_iter().collect(),
+                }),
+                _ => {}
+            }
+        });
+        if let Some(idle) = idle {
+            self.global().entry(key).or_default().push(idle);
+        }
+    }
+}
+
+/// Releases the physical memory of an idle stack, keeping the address range
+/// valid.
+#[cfg(unix)]
+fn decommit(stack: &Stack) {
+    let page = crate::page_size();
+    let start = stack.base().addr().get();
+    let end = start + stack.layout().size();
+    let (start, end) = (start.next_multiple_of(page), end & !(page - 1));
+    if start < end {
+        let ptr = stack.base().as_ptr().with_addr(start);
+        // SAFETY: The pages are within the stack, and the contents are not used
+        // any longer.
+        unsafe { libc::madvise(ptr.cast(), end - start, libc::MADV_DONTNEED) };
+    }
+}
+
+#[cfg(not(unix))]
+fn decommit(_: &Stack) {}
+
+/// The record of a stack lent by a pool, saved on top of its usable memory.
+struct Header {
+    inner: Stack,
+    shared: Arc<Shared>,
+    key: Key,
+}
+
+unsafe fn recycle(memory: NonNull<u8>, returned: Layout) {
+    // SAFETY: The header is written in `allocate`, and never modified.
+    let Header { inner, shared, key } =
+        unsafe { header_in::<Header>(memory, returned).read() };
+    shared.put(key, inner);
+}
+
+/// The stack allocator that recycles stacks allocated by `A`.
+///
+/// Dropped stacks are kept in free lists keyed by the requested layout, first
+/// in a small cache of the current thread, and then in a list shared by all
+/// threads. A stack is freed to `A` instead if the total size of idle stacks
+/// would exceed the limit.
+///
+/// The pool can be constructed in constant contexts, so that it can be
+/// installed with [`global_stack_allocator`](crate::global_stack_allocator):
+///
+/// ```
+/// # #![feature(allocator_api)]
+/// use std::alloc::Global;
+///
+/// use unico_stack::StackPool;
+///
+/// static POOL: StackPool<Global> = StackPool::new(Global).max_retained(1 << 20);
+/// unico_stack::global_stack_allocator!(POOL);
+/// ```
+pub struct StackPool<A> {
+    alloc: A,
+    config: Config,
+    shared: OnceLock<Arc<Shared>>,
+}
+
+impl<A> StackPool<A> {
+    /// Creates a pool over `alloc`, which retains up to 64 MiB of idle stacks,
+    /// with up to 16 stacks of each layout cached in each thread.
+    pub const fn new(alloc: A) -> Self {
+        StackPool {
+            alloc,
+            config: Config {
+                max_retained: 64 << 20,
+                local_capacity: 16,
+                decommit: false,
+            },
+            shared: OnceLock::new(),
+        }
+    }
+
+    /// Sets the maximum total size of idle stacks retained in the pool.
+    pub const fn max_retained(mut self, bytes: usize) -> Self {
+        self.config.max_retained = bytes;
+        self
+    }
+
+    /// Sets the maximum number of idle stacks of each layout cached in each
+    /// thread.
+    pub const fn local_capacity(mut self, stacks: usize) -> Self {
+        self.config.local_capacity = stacks;
+        self
+    }
+
+    /// Sets whether the physical memory of idle stacks is released with
+    /// `madvise(MADV_DONTNEED)`, at the cost of page faults when reused.
+    ///
+    /// This is a no-op on non-Unix targets.
+    pub const fn decommit_idle(mut self, decommit: bool) -> Self {
+        self.config.decommit = decommit;
+        self
+    }
+
+    fn shared(&self) -> &Arc<Shared> {
+        self.shared.get_or_init(|| {
+            Arc::new(Shared {
+                config: self.config,
+                closed: AtomicBool::new(false),
+                global: Mutex::new(BTreeMap::new()),
+                hits: AtomicUsize::new(0),
+                misses: AtomicUsize::new(0),
+                retained: AtomicUsize::new(0),
+            })
+        })
+    }
+
+    /// The statistics of the pool so far.
+    pub fn stats(&self) -> PoolStats {
+        match self.shared.get() {
+            Some(shared) => PoolStats {
+                hits: shared.hits.load(Relaxed),
+                misses: shared.misses.load(Relaxed),
+                retained: shared.retained.load(Relaxed),
+            },
+            None => PoolStats::default(),
+        }
+    }
+}
+
+/// Frees the idle stacks of the pool in the global list and the cache of the
+/// current thread. The caches of other threads are evicted the next time they
+/// are used, or when the threads exit. Stacks still in use are freed when
+/// dropped.
+impl<A> Drop for StackPool<A> {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.get() else {
+            return;
+        };
+        shared.closed.store(true, Relaxed);
+        evict();
+        let global = mem::take(&mut *shared.global());
+        for idle in global.into_values().flatten() {
+            shared.retained.fetch_sub(idle.size(), Relaxed);
+        }
+    }
+}
+
+impl<A: fmt::Debug> fmt::Debug for StackPool<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StackPool")
+            .field("alloc", &self.alloc)
+            .field("config", &self.config)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+// SAFETY: The stacks are allocated by `A`, and only returned to the pool by the
+// dropper, with the original stacks saved on top of the usable memory.
+unsafe impl<A: StackAllocator> StackAllocator for StackPool<A> {
+    fn allocate(&self, layout: Layout) -> Result<Stack, AllocError> {
+        let shared = self.shared();
+        let key = (layout.size(), layout.align());
+
+        let inner = match shared.take(key) {
+            Some(inner) => inner,
+            None => {
+                let inner = self.alloc.allocate(inner_layout::<Header>(layout)?)?;
+                shared.misses.fetch_add(1, Relaxed);
+                inner
+            }
+        };
+
+        let header = |inner| Header {
+            inner,
+            shared: shared.clone(),
+            key,
+        };
+        // SAFETY: The inner stack is aligned for the header, and returned to the
+        // pool by `recycle`.
+        unsafe { with_header(inner, layout, header, recycle) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        alloc::{AllocError, Allocator, Layout},
+        ptr::NonNull,
+        sync::atomic::{AtomicUsize, Ordering::Relaxed},
+    };
+    use std::{alloc::Global, thread};
+
+    use super::{PoolStats, StackPool};
+    use crate::{DEFAULT_LAYOUT, StackAllocator};
+
+    /// Counts the live allocations of the global allocator.
+    #[derive(Clone, Copy)]
+    struct Counted(&'static AtomicUsize);
+
+    // SAFETY: Every operation is forwarded to `Global`.
+    unsafe impl Allocator for Counted {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.fetch_add(1, Relaxed);
+            Allocator::allocate(&Global, layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(1, Relaxed);
+            // SAFETY: The contract is the same.
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    #[test]
+    fn recycle() {
+        let pool = StackPool::new(Global);
+        let stack = pool.allocate(DEFAULT_LAYOUT).unwrap();
+        assert!(stack.layout().size() >= DEFAULT_LAYOUT.size());
+        let base = stack.base();
+        drop(stack);
+        let retained = pool.stats().retained;
+        assert!(retained > DEFAULT_LAYOUT.size());
+
+        let stack = pool.allocate(DEFAULT_LAYOUT).unwrap();
+        assert_eq!(stack.base(), base);
+        // Another layout misses.
+        let small = Layout::from_size_align(4096, 16).unwrap();
+        let other = pool.allocate(small).unwrap();
+        let stats = pool.stats();
+        assert_eq!(
+            stats,
+            PoolStats {
+                hits: 1,
+                misses: 2,
+                retained: 0
+            }
+        );
+        drop((stack, other));
+    }
+
+    #[test]
+    fn global() {
+        static POOL: StackPool<Global> = StackPool::new(Global).local_capacity(0);
+
+        let stack = POOL.allocate(DEFAULT_LAYOUT).unwrap();
+        let base = stack.base().addr();
+        drop(stack);
+        // The stack is shared with other threads.
+        thread::spawn(move || {
+            let stack = POOL.allocate(DEFAULT_LAYOUT).unwrap();
+            assert_eq!(stack.base().addr(), base);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(POOL.stats().hits, 1);
+    }
+
+    #[test]
+    fn drop_pool() {
+        static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+        let pool = StackPool::new(Counted(&LIVE));
+        let (idle, used) = (
+            pool.allocate(DEFAULT_LAYOUT).unwrap(),
+            pool.allocate(DEFAULT_LAYOUT).unwrap(),
+        );
+        drop(idle);
+        assert_eq!(LIVE.load(Relaxed), 2);
+
+        // The idle stack cached in this thread is freed with the pool, and the
+        // one in use is freed instead of being retained.
+        drop(pool);
+        assert_eq!(LIVE.load(Relaxed), 1);
+        drop(used);
+        assert_eq!(LIVE.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn max_retained() {
+        let pool = StackPool::new(Global).max_retained(DEFAULT_LAYOUT.size() * 3);
+        let stacks: [_; 4] =
+            core::array::from_fn(|_| pool.allocate(DEFAULT_LAYOUT).unwrap());
+        drop(stacks);
+        let stats = pool.stats();
+        assert_eq!(stats.misses, 4);
+        assert!(stats.retained <= DEFAULT_LAYOUT.size() * 3);
+        assert!(stats.retained > DEFAULT_LAYOUT.size() * 2);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn decommit() {
+        let pool = StackPool::new(Global).decommit_idle(true);
+        let stack = pool.allocate(DEFAULT_LAYOUT).unwrap();
+        let (base, size) = (stack.base().as_ptr(), stack.layout().size());
+        // SAFETY: The memory is valid for `stack.layout()`.
+        unsafe { base.write_bytes(0xcc, size) };
+        drop(stack);
+
+        let stack = pool.allocate(DEFAULT_LAYOUT).unwrap();
+        assert_eq!(stack.base().as_ptr(), base);
+        // The whole pages within the stack are zeroed.
+        let page = crate::page_size();
+        let start = base.addr().next_multiple_of(page) - base.addr();
+        // SAFETY: Same as above.
+        assert_eq!(unsafe { base.add(start).read() }, 0);
+    }
+}